@@ -0,0 +1,92 @@
+//! Stand-in for a service receiving the webhooks of the bot
+//!
+//! Prints every event with a valid signature and rejects the others with 401.
+//! The first `fail` requests are answered with 500 to try out retries.
+//! The bot only sends to https URLs of public hosts unless `webhooks_allow_private`
+//! is enabled under `[features]` in its config, then `http://127.0.0.1:<port>` works.
+//!
+//! Usage: cargo run --example webhook_receiver -- <address> <secret> [fail]
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use ring::hmac;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let (Some(address), Some(secret)) = (args.next(), args.next()) else {
+        eprintln!("Usage: webhook_receiver <address> <secret> [fail]");
+        return;
+    };
+    let mut fail: u32 = args.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let listener = TcpListener::bind(&address).unwrap();
+    println!("Listening on {address}");
+    for stream in listener.incoming() {
+        let status = match stream.map(|stream| handle(stream, &key, &mut fail)) {
+            Ok(Ok(status)) => status,
+            Ok(Err(err)) | Err(err) => {
+                eprintln!("{err}");
+                continue;
+            }
+        };
+        println!("Answered {status}");
+    }
+}
+
+/// Reads one request and answers it, returns the status line that was sent
+fn handle(stream: TcpStream, key: &hmac::Key, fail: &mut u32) -> std::io::Result<&'static str> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    let (mut length, mut signature) = (0, String::new());
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => length = value.trim().parse().unwrap_or(0),
+                "x-voicetime-signature" => signature = value.trim().to_string(),
+                _ => {}
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let status = if *fail > 0 {
+        *fail -= 1;
+        "500 Internal Server Error"
+    } else if verify(key, &body, &signature) {
+        println!("{}", String::from_utf8_lossy(&body));
+        "200 OK"
+    } else {
+        eprintln!("Invalid signature {signature:?}");
+        "401 Unauthorized"
+    };
+    write!(
+        reader.get_mut(),
+        "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )?;
+    Ok(status)
+}
+
+/// Checks a `sha256=<hex>` signature in constant time
+fn verify(key: &hmac::Key, body: &[u8], signature: &str) -> bool {
+    let Some(hex) = signature.strip_prefix("sha256=") else {
+        return false;
+    };
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect();
+    bytes.is_some_and(|bytes| hmac::verify(key, body, &bytes).is_ok())
+}
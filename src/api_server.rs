@@ -0,0 +1,404 @@
+//! Read-only HTTP API serving voice statistics as JSON
+//!
+//! - `GET /guilds/{guild}/leaderboard?channel=&period=&limit=&offset=`
+//! - `GET /guilds/{guild}/users/{user}?channel=&period=`
+//! - `GET /metrics` in the Prometheus text format
+//! - `GET /events` and `GET /guilds/{guild}/events` as Server-Sent Events
+//!
+//! `period` takes the same names as the slash commands (`all`, `week`, `2023-10`, ...).
+//! Ids are returned as strings, they don't fit into a JavaScript number.
+//! Users that opted out are never returned, in events they are redacted to `null`.
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use serde_json::{json, Value};
+use serenity::model::prelude::{ChannelId, GuildId, UserId};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::error::RecvError,
+};
+use tracing::error;
+
+use crate::{
+    config::Features,
+    control_server::read_line,
+    db::{DbManager, VoiceEvent},
+    metrics::METRICS,
+    period::Period,
+};
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 100;
+/// How often event streams get a snapshot of everyone in voice
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+/// Longest request or header line accepted
+const MAX_LINE: usize = 8192;
+/// How long a client may take to send the request and its headers
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Starts the API server on `address`, `/metrics` and `/events` only if their feature is enabled
+pub async fn create_api_server(
+    address: SocketAddr,
+    features: Features,
+    db: Arc<DbManager>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, features, db.clone()));
+                }
+                Err(err) => error!("{err}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// A response that couldn't be served, with its status line and message
+#[derive(Debug)]
+struct ApiError(&'static str, String);
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self("400 Bad Request", message.into())
+    }
+    fn not_found(message: impl Into<String>) -> Self {
+        Self("404 Not Found", message.into())
+    }
+}
+
+/// Body of a successful response
+enum Body {
+    Json(Value),
+    Metrics(String),
+    /// A stream of events of one guild or all
+    Events(Option<GuildId>),
+}
+
+/// Serves a single request, the connection is closed afterwards
+async fn handle_connection(stream: TcpStream, features: Features, db: Arc<DbManager>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let request = read_line(&mut reader, MAX_LINE).await?;
+        // The headers aren't needed, but have to be read before responding
+        while let Some(header) = read_line(&mut reader, MAX_LINE).await? {
+            if header.is_empty() {
+                break;
+            }
+        }
+        io::Result::Ok(request)
+    })
+    .await;
+    let Ok(Ok(Some(request))) = request else {
+        return;
+    };
+    let (status, body) = match handle_request(&request, features, &db).await {
+        Ok(body) => ("200 OK", body),
+        Err(ApiError(status, message)) => (status, Body::Json(json!({ "error": message }))),
+    };
+    let (content_type, body) = match body {
+        Body::Json(value) => ("application/json", value.to_string()),
+        Body::Metrics(text) => ("text/plain; version=0.0.4", text),
+        Body::Events(guild) => return stream_events(writer, &db, guild).await,
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    if let Err(err) = writer.write_all(response.as_bytes()).await {
+        error!("{err}");
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Routes a request line like `GET /guilds/1/leaderboard HTTP/1.1`
+async fn handle_request(
+    request: &str,
+    features: Features,
+    db: &DbManager,
+) -> Result<Body, ApiError> {
+    let mut parts = request.split(' ');
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if method != "GET" {
+        return Err(ApiError(
+            "405 Method Not Allowed",
+            "only GET is supported".into(),
+        ));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = Query::parse(query)?;
+    let segments: Vec<_> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match segments[..] {
+        ["metrics"] if features.metrics => {
+            Ok(Body::Metrics(METRICS.render(&db.open_sessions().await)))
+        }
+        ["events"] if features.live_events => Ok(Body::Events(None)),
+        ["guilds", guild, "events"] if features.live_events => {
+            Ok(Body::Events(Some(GuildId(parse_id(guild)?))))
+        }
+        ["guilds", guild, "leaderboard"] => leaderboard(parse_id(guild)?, query, db)
+            .await
+            .map(Body::Json),
+        ["guilds", guild, "users", user] => {
+            user_stats(parse_id(guild)?, parse_id(user)?, query, db)
+                .await
+                .map(Body::Json)
+        }
+        _ => Err(ApiError::not_found(format!("no route for {path}"))),
+    }
+}
+
+async fn leaderboard(guild: u64, query: Query, db: &DbManager) -> Result<Value, ApiError> {
+    let leaderboard = db
+        .leaderboard_data(GuildId(guild), query.channel, query.period)
+        .await
+        .ok_or_else(|| ApiError::not_found("unknown guild"))?;
+    let entries: Vec<_> = leaderboard
+        .iter()
+        .enumerate()
+        .skip(query.offset)
+        .take(query.limit)
+        .map(|(index, (user, seconds))| {
+            json!({
+                "position": index + 1,
+                "user": user.0.to_string(),
+                "seconds": seconds,
+            })
+        })
+        .collect();
+    Ok(json!({
+        "guild": guild.to_string(),
+        "channel": query.channel.map(|c| c.0.to_string()),
+        "period": query.period.name(),
+        "total": leaderboard.len(),
+        "offset": query.offset,
+        "entries": entries,
+    }))
+}
+
+async fn user_stats(
+    guild: u64,
+    user: u64,
+    query: Query,
+    db: &DbManager,
+) -> Result<Value, ApiError> {
+    let stats = db
+        .user_stats(UserId(user), GuildId(guild), query.channel, query.period)
+        .await
+        .ok_or_else(|| ApiError::not_found("unknown guild or user"))?;
+    Ok(json!({
+        "guild": guild.to_string(),
+        "user": user.to_string(),
+        "channel": query.channel.map(|c| c.0.to_string()),
+        "period": query.period.name(),
+        "seconds": stats.seconds,
+        "audience_seconds": stats.audience_seconds,
+        "position": stats.position,
+        "total": stats.total,
+    }))
+}
+
+/// Sends a snapshot and then every [VoiceEvent] until the client disconnects
+///
+/// Snapshots are repeated every [SNAPSHOT_INTERVAL] and whenever events had to be dropped
+async fn stream_events(
+    mut writer: impl AsyncWrite + Unpin,
+    db: &DbManager,
+    guild: Option<GuildId>,
+) {
+    // Subscribing before the first snapshot, so no event in between is missed
+    let mut events = db.subscribe();
+    let header = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    if writer.write_all(header.as_bytes()).await.is_err() {
+        return;
+    }
+    let mut snapshots = tokio::time::interval(SNAPSHOT_INTERVAL);
+    loop {
+        let (name, data) = tokio::select! {
+            _ = snapshots.tick() => ("snapshot", snapshot(db, guild).await),
+            event = events.recv() => match event {
+                Ok(event) if guild.is_none_or(|guild| guild == event.guild()) => {
+                    event_json(event)
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => ("snapshot", snapshot(db, guild).await),
+                Err(RecvError::Closed) => break,
+            },
+        };
+        let message = format!("event: {name}\ndata: {data}\n\n");
+        if writer.write_all(message.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn snapshot(db: &DbManager, guild: Option<GuildId>) -> Value {
+    let sessions: Vec<_> = db
+        .live_sessions(guild)
+        .await
+        .into_iter()
+        .map(|session| {
+            json!({
+                "guild": session.guild.0.to_string(),
+                "channel": session.channel.0.to_string(),
+                "user": session.user.map(|u| u.0.to_string()),
+                "seconds": session.duration,
+            })
+        })
+        .collect();
+    json!({ "sessions": sessions })
+}
+
+/// Name and data of the Server-Sent Event for a [VoiceEvent]
+fn event_json(event: VoiceEvent) -> (&'static str, Value) {
+    let id = |id: u64| id.to_string();
+    match event {
+        VoiceEvent::Join {
+            guild,
+            channel,
+            user,
+        } => (
+            "join",
+            json!({ "guild": id(guild.0), "channel": id(channel.0), "user": user.map(|u| id(u.0)) }),
+        ),
+        VoiceEvent::Leave {
+            guild,
+            channel,
+            user,
+        } => (
+            "leave",
+            json!({ "guild": id(guild.0), "channel": id(channel.0), "user": user.map(|u| id(u.0)) }),
+        ),
+        VoiceEvent::Move {
+            guild,
+            from,
+            to,
+            user,
+        } => (
+            "move",
+            json!({
+                "guild": id(guild.0),
+                "from": id(from.0),
+                "to": id(to.0),
+                "user": user.map(|u| id(u.0)),
+            }),
+        ),
+    }
+}
+
+/// Parameters shared by the endpoints
+struct Query {
+    channel: Option<ChannelId>,
+    period: Period,
+    limit: usize,
+    offset: usize,
+}
+
+impl Query {
+    /// Parses a query string like `channel=1&period=week`, unknown parameters are ignored
+    fn parse(query: &str) -> Result<Self, ApiError> {
+        let mut parsed = Query {
+            channel: None,
+            period: Period::All,
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+        };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if value.is_empty() {
+                continue;
+            }
+            match key {
+                "channel" => parsed.channel = Some(ChannelId(parse_id(value)?)),
+                "period" => {
+                    parsed.period = Period::from_name(value)
+                        .ok_or_else(|| ApiError::bad_request(format!("unknown period {value}")))?
+                }
+                "limit" => match value.parse() {
+                    Ok(limit) if (1..=MAX_LIMIT).contains(&limit) => parsed.limit = limit,
+                    _ => {
+                        return Err(ApiError::bad_request(format!(
+                            "limit has to be between 1 and {MAX_LIMIT}"
+                        )))
+                    }
+                },
+                "offset" => {
+                    parsed.offset = value
+                        .parse()
+                        .map_err(|_| ApiError::bad_request("offset has to be a number"))?
+                }
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn parse_id(value: &str) -> Result<u64, ApiError> {
+    value
+        .parse()
+        .map_err(|_| ApiError::bad_request(format!("invalid id {value}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(query: &str) -> String {
+        match Query::parse(query) {
+            Ok(_) => panic!("{query} was accepted"),
+            Err(ApiError(status, message)) => {
+                assert_eq!(status, "400 Bad Request");
+                message
+            }
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let query = Query::parse("").unwrap();
+        assert_eq!(query.channel, None);
+        assert_eq!(query.period, Period::All);
+        assert_eq!((query.limit, query.offset), (DEFAULT_LIMIT, 0));
+    }
+
+    #[test]
+    fn every_parameter() {
+        let query = Query::parse("channel=123&period=2023-10&limit=25&offset=50").unwrap();
+        assert_eq!(query.channel, Some(ChannelId(123)));
+        assert_eq!(
+            query.period,
+            Period::Named {
+                year: 2023,
+                month: 10
+            }
+        );
+        assert_eq!((query.limit, query.offset), (25, 50));
+    }
+
+    #[test]
+    fn empty_and_unknown_parameters_are_ignored() {
+        let query = Query::parse("&channel=&period&sort=time&&limit=100").unwrap();
+        assert_eq!(query.channel, None);
+        assert_eq!(query.period, Period::All);
+        assert_eq!(query.limit, MAX_LIMIT);
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        assert_eq!(parse_error("channel=general"), "invalid id general");
+        assert_eq!(parse_error("period=fortnight"), "unknown period fortnight");
+        assert_eq!(parse_error("period=2023-13"), "unknown period 2023-13");
+        for limit in ["0", "101", "ten", "-1"] {
+            assert_eq!(
+                parse_error(&format!("limit={limit}")),
+                "limit has to be between 1 and 100"
+            );
+        }
+        assert_eq!(parse_error("offset=-1"), "offset has to be a number");
+    }
+}
@@ -0,0 +1,130 @@
+//! Command line client for the control server of the bot
+//!
+//! Connects to `CONTROL_SOCKET` if it is set, otherwise to `CONTROL_ADDRESS`
+//! authenticating with `CONTROL_TOKEN`. Both can be given as options as well.
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    os::unix::net::UnixStream,
+    process::ExitCode,
+};
+
+const USAGE: &str = "\
+Usage: voicetimectl [--socket <path> | --address <address>] <command>
+
+Commands:
+  save                         Save the DB
+  stop                         Close all sessions, save the DB and stop the bot
+  status                       Show uptime, guilds, active sessions and the last save
+  sessions                     List sessions going on: user guild channel seconds
+  user <user id>               Total seconds of a user: guild channel seconds
+  export                       Total seconds of all users: user guild channel seconds
+  optout add|remove <user id>  Opt a user out of or back into tracking";
+
+/// Commands answered with `OK <count>` followed by one line per entry
+const LIST_COMMANDS: [&str; 3] = ["sessions", "user", "export"];
+
+fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+    let mut socket = env::var("CONTROL_SOCKET").ok().filter(|s| !s.is_empty());
+    let mut address = env::var("CONTROL_ADDRESS").ok().filter(|a| !a.is_empty());
+    let mut args = env::args().skip(1).peekable();
+    while let Some(option) = args.next_if(|arg| arg.starts_with("--")) {
+        match (option.as_str(), args.next()) {
+            ("--socket", Some(path)) => socket = Some(path),
+            ("--address", Some(value)) => {
+                address = Some(value);
+                socket = None;
+            }
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let command = args.collect::<Vec<_>>().join(" ");
+    if command.is_empty() {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+    let result = match socket {
+        Some(path) => UnixStream::connect(&path)
+            .map_err(|err| format!("Connecting to {path} failed: {err}"))
+            .and_then(|stream| run(stream, None, &command)),
+        None => {
+            let address = address.unwrap_or_else(|| "127.0.0.1:9500".to_string());
+            let Some(token) = env::var("CONTROL_TOKEN").ok().filter(|t| !t.is_empty()) else {
+                eprintln!("CONTROL_TOKEN has to be set to connect to {address}");
+                return ExitCode::FAILURE;
+            };
+            TcpStream::connect(&address)
+                .map_err(|err| format!("Connecting to {address} failed: {err}"))
+                .and_then(|stream| run(stream, Some(&token), &command))
+        }
+    };
+    match result {
+        Ok(lines) => {
+            for line in lines {
+                println!("{line}");
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Sends `command` and returns the lines of the response without the `OK`
+fn run(
+    stream: impl Read + Write,
+    token: Option<&str>,
+    command: &str,
+) -> Result<Vec<String>, String> {
+    let mut connection = Connection {
+        reader: BufReader::new(stream),
+    };
+    if let Some(token) = token {
+        connection.send(&format!("auth {token}"))?;
+        connection.response()?;
+    }
+    connection.send(command)?;
+    let details = connection.response()?;
+    let name = command.split(' ').next().unwrap_or_default();
+    if !LIST_COMMANDS.contains(&name) {
+        return Ok(vec![details]);
+    }
+    let count: usize = details
+        .parse()
+        .map_err(|_| format!("Unexpected response: OK {details}"))?;
+    (0..count).map(|_| connection.line()).collect()
+}
+
+struct Connection<S> {
+    reader: BufReader<S>,
+}
+
+impl<S: Read + Write> Connection<S> {
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.reader.get_mut(), "{line}").map_err(|err| err.to_string())
+    }
+    fn line(&mut self) -> Result<String, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Err("The connection was closed".to_string()),
+            Ok(_) => Ok(line.trim_end().to_string()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+    /// Reads an `OK <details>` line and returns the details, `ERR <reason>` is an error
+    fn response(&mut self) -> Result<String, String> {
+        let line = self.line()?;
+        if let Some(details) = line.strip_prefix("OK") {
+            return Ok(details.trim_start().to_string());
+        }
+        Err(line.strip_prefix("ERR ").unwrap_or(&line).to_string())
+    }
+}
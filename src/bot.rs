@@ -1,189 +1,553 @@
 use std::sync::Arc;
 
+use chrono_tz::Tz;
 use serenity::async_trait;
+use serenity::model::channel::{Channel, ChannelType, GuildChannel};
 use serenity::model::gateway::Ready;
-use serenity::model::prelude::application_command::CommandDataOptionValue;
-use serenity::model::prelude::command::{Command, CommandType};
-use serenity::model::prelude::{ChannelType, Interaction, InteractionResponseType, UserId};
+use serenity::model::guild::{Guild, Member, UnavailableGuild};
+use serenity::model::prelude::application_command::{
+    ApplicationCommandInteraction, CommandDataOption, ResolvedTarget,
+};
+use serenity::model::prelude::command::CommandType;
+use serenity::model::prelude::{ChannelId, GuildId, Interaction, RoleId, UserId};
+use serenity::model::user::User;
 use serenity::model::voice::VoiceState;
 use serenity::prelude::*;
 
-use crate::db::DbManager;
+use crate::commands;
+use crate::config::Features;
+use crate::db::{
+    AdminAction, AutocompleteKind, DbManager, DepartedPolicy, LeaderboardFormat, RoleExclusion,
+};
+use crate::format::TimeFormat;
+use crate::i18n::{Language, Reply};
+use crate::period::Period;
+use crate::webhook::{self, Webhook};
+use tracing::info;
 
 struct Handler {
     db: Arc<DbManager>,
+    /// Guilds commands are registered in instead of globally
+    dev_guilds: Vec<GuildId>,
+    /// Features enabled in the config, like the guild members intent
+    features: Features,
 }
 
 impl Handler {
-    fn new(db: Arc<DbManager>) -> Self {
-        Self { db }
+    fn new(db: Arc<DbManager>, dev_guilds: Vec<GuildId>, features: Features) -> Self {
+        Self {
+            db,
+            dev_guilds,
+            features,
+        }
+    }
+    /// Remembers the name and category of voice and stage channels
+    fn update_channel(&self, channel: &GuildChannel) {
+        if matches!(channel.kind, ChannelType::Voice | ChannelType::Stage) {
+            self.db.update_channel(
+                channel.guild_id,
+                channel.id,
+                channel.name.clone(),
+                channel.parent_id,
+            );
+        }
+    }
+    /// The user and message context menu commands, both show the voice time of a user
+    fn handle_context_menu(&self, ctx: Context, command: ApplicationCommandInteraction) {
+        if command.data.name != commands::VOICE_TIME_MENU {
+            return;
+        }
+        let user = match command.data.target() {
+            Some(ResolvedTarget::User(user, _)) => user.id,
+            Some(ResolvedTarget::Message(message)) => message.author.id,
+            _ => return,
+        };
+        self.db.get_time(
+            user,
+            command.guild_id.unwrap(),
+            None,
+            Period::All,
+            ctx.http,
+            command,
+        );
+    }
+    /// Parses the shared options, users and channels have to be picked from the suggestions
+    async fn query(
+        &self,
+        guild_id: GuildId,
+        options: &[CommandDataOption],
+    ) -> Result<Query, Reply> {
+        let query = Query::from_options(options)?;
+        self.db
+            .check_options(guild_id, query.user, query.channel)
+            .await?;
+        Ok(query)
+    }
+    fn handle_settings(&self, ctx: Context, command: ApplicationCommandInteraction) {
+        let guild_id = command.guild_id.unwrap();
+        let Some(subcommand) = command.data.options.first() else {
+            return;
+        };
+        let reply = match subcommand.name.as_str() {
+            "timezone" => match string_option(&subcommand.options, "name").map(str::parse::<Tz>) {
+                Some(Ok(timezone)) => {
+                    self.db.set_timezone(guild_id, timezone);
+                    Reply::TimezoneSet(timezone)
+                }
+                _ => Reply::UnknownTimezone,
+            },
+            "time_format" => {
+                match string_option(&subcommand.options, "style").and_then(TimeFormat::from_name) {
+                    Some(time_format) => {
+                        self.db.set_time_format(guild_id, time_format);
+                        Reply::TimeFormatSet(time_format)
+                    }
+                    None => Reply::UnknownTimeFormat,
+                }
+            }
+            "language" => {
+                let language =
+                    string_option(&subcommand.options, "language").and_then(Language::from_code);
+                self.db.set_language(guild_id, language);
+                match language {
+                    Some(language) => Reply::LanguageSet(language),
+                    None => Reply::LanguageReset,
+                }
+            }
+            "exclude_role" => {
+                let options = &subcommand.options;
+                let Some(role) = string_option(options, "role")
+                    .and_then(parse_id)
+                    .map(RoleId)
+                else {
+                    return;
+                };
+                let exclusion = match string_option(options, "mode") {
+                    Some("tracking") => Some(RoleExclusion::Tracking),
+                    Some("leaderboard") => Some(RoleExclusion::Leaderboard),
+                    _ => None,
+                };
+                self.db.set_role_exclusion(guild_id, role, exclusion);
+                match exclusion {
+                    Some(exclusion) => Reply::RoleExcluded {
+                        role,
+                        tracking: exclusion == RoleExclusion::Tracking,
+                    },
+                    None => Reply::RoleIncluded(role),
+                }
+            }
+            "departed" => {
+                let options = &subcommand.options;
+                let policy = match string_option(options, "policy") {
+                    Some("hide") => DepartedPolicy::Hide,
+                    Some("delete") => DepartedPolicy::Delete {
+                        days: integer_option(options, "days").unwrap_or(30) as u64,
+                    },
+                    _ => DepartedPolicy::Keep,
+                };
+                self.db.set_departed_policy(guild_id, policy);
+                Reply::DepartedPolicySet(policy)
+            }
+            "webhook" => {
+                let options = &subcommand.options;
+                let url = string_option(options, "url");
+                let secret = string_option(options, "secret").filter(|s| !s.is_empty());
+                match (url, secret) {
+                    (None, _) => {
+                        self.db.set_webhook(guild_id, None);
+                        Reply::WebhookDisabled
+                    }
+                    (Some(url), Some(secret))
+                        if webhook::is_allowed_url(url, self.features.webhooks_allow_private) =>
+                    {
+                        let webhook = Webhook {
+                            url: url.to_string(),
+                            secret: secret.to_string(),
+                        };
+                        self.db.set_webhook(guild_id, Some(webhook));
+                        Reply::WebhookSet(url.to_string())
+                    }
+                    _ => Reply::InvalidWebhook,
+                }
+            }
+            "archive_deleted" => {
+                let archive = boolean_option(&subcommand.options, "enabled").unwrap_or(false);
+                self.db.set_archive_deleted_channels(guild_id, archive);
+                Reply::ArchiveDeletedChannels(archive)
+            }
+            "max_session" => {
+                let options = &subcommand.options;
+                let close = boolean_option(options, "close").unwrap_or(false);
+                match string_option(options, "duration") {
+                    Some("off") => {
+                        self.db.set_max_session(guild_id, None, false);
+                        Reply::MaxSessionDisabled
+                    }
+                    duration => match duration.and_then(TimeFormat::parse_signed) {
+                        Some(seconds) if seconds > 0 => {
+                            let seconds = seconds as u64;
+                            self.db.set_max_session(guild_id, Some(seconds), close);
+                            Reply::MaxSessionSet { seconds, close }
+                        }
+                        _ => Reply::InvalidDuration,
+                    },
+                }
+            }
+            _ => return,
+        };
+        self.db.reply(reply, ctx.http, command);
     }
+    /// Corrections may concern users and channels without recorded time,
+    /// so the options aren't checked against the suggestions
+    fn handle_admin(&self, ctx: Context, command: ApplicationCommandInteraction) {
+        let guild_id = command.guild_id.unwrap();
+        let Some(subcommand) = command.data.options.first() else {
+            return;
+        };
+        let options = &subcommand.options;
+        let limit = integer_option(options, "limit").unwrap_or(10) as usize;
+        match subcommand.name.as_str() {
+            "audit" => return self.db.get_audit_log(guild_id, limit, ctx.http, command),
+            "flagged" => {
+                return self
+                    .db
+                    .get_flagged_sessions(guild_id, limit, ctx.http, command)
+            }
+            _ => {}
+        }
+        let action = match Query::from_options(options) {
+            Ok(query) => match (subcommand.name.as_str(), query.user, query.channel) {
+                ("adjust_time", Some(user), Some(channel)) => {
+                    match string_option(options, "duration").and_then(TimeFormat::parse_signed) {
+                        Some(seconds) => Ok(AdminAction::AdjustTime {
+                            user,
+                            channel,
+                            seconds,
+                        }),
+                        None => Err(Reply::InvalidDuration),
+                    }
+                }
+                ("reset_user", Some(user), _) => Ok(AdminAction::ResetUser { user }),
+                ("reset_channel", _, Some(channel)) => Ok(AdminAction::ResetChannel { channel }),
+                _ => return,
+            },
+            Err(reply) => Err(reply),
+        };
+        let reply = match action {
+            Ok(action) => {
+                let reason = string_option(options, "reason").unwrap_or_default();
+                self.db
+                    .admin_action(guild_id, command.user.id, action, reason.to_string());
+                match action {
+                    AdminAction::AdjustTime {
+                        user,
+                        channel,
+                        seconds,
+                    } => Reply::TimeAdjusted {
+                        user,
+                        channel,
+                        seconds,
+                    },
+                    AdminAction::ResetUser { user } => Reply::UserReset(user),
+                    AdminAction::ResetChannel { channel } => Reply::ChannelReset(channel),
+                }
+            }
+            Err(reply) => reply,
+        };
+        self.db.reply(reply, ctx.http, command);
+    }
+}
+
+/// The user, channel and period options most commands share
+struct Query {
+    user: Option<UserId>,
+    channel: Option<ChannelId>,
+    period: Period,
+}
+
+impl Query {
+    /// Parses the options, which are plain strings so they can be autocompleted
+    fn from_options(options: &[CommandDataOption]) -> Result<Self, Reply> {
+        let user = string_option(options, "user")
+            .map(|v| parse_id(v).map(UserId).ok_or(Reply::UnknownUser))
+            .transpose()?;
+        let channel = string_option(options, "channel")
+            .map(|v| parse_id(v).map(ChannelId).ok_or(Reply::UnknownChannel))
+            .transpose()?;
+        let period = string_option(options, "period")
+            .map(|v| Period::from_name(v).ok_or(Reply::UnknownPeriod))
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Self {
+            user,
+            channel,
+            period,
+        })
+    }
+}
+
+/// Parses an id given plainly or as mention like `<@123>` or `<#123>`
+fn parse_id(value: &str) -> Option<u64> {
+    value
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .trim_start_matches(['@', '!', '#'])
+        .parse()
+        .ok()
+}
+
+/// The option that is being autocompleted, options of subcommands are searched as well
+fn focused_option(
+    options: &[CommandDataOption],
+) -> Option<(&CommandDataOption, &[CommandDataOption])> {
+    for option in options {
+        if option.focused {
+            return Some((option, options));
+        }
+        if let Some(focused) = focused_option(&option.options) {
+            return Some(focused);
+        }
+    }
+    None
+}
+
+fn integer_option(options: &[CommandDataOption], name: &str) -> Option<i64> {
+    options
+        .iter()
+        .find(|v| v.name == name)
+        .and_then(|v| v.value.as_ref())
+        .and_then(|v| v.as_i64())
+}
+
+fn boolean_option(options: &[CommandDataOption], name: &str) -> Option<bool> {
+    options
+        .iter()
+        .find(|v| v.name == name)
+        .and_then(|v| v.value.as_ref())
+        .and_then(|v| v.as_bool())
+}
+
+fn string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|v| v.name == name)
+        .and_then(|v| v.value.as_ref())
+        .and_then(|v| v.as_str())
 }
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, ctx: Context, ready: Ready) {
-        Command::create_global_application_command(&ctx.http, |command| {
-            command
-                .name("opt_out")
-                .kind(CommandType::ChatInput)
-                .dm_permission(false)
-                .description("Opt out of voice chat data aggregation")
-        })
-        .await
-        .unwrap();
-        Command::create_global_application_command(&ctx.http, |command| {
-            command
-                .name("opt_in")
-                .kind(CommandType::ChatInput)
-                .dm_permission(false)
-                .description("Opt into voice chat data aggregation")
-        })
-        .await
-        .unwrap();
-        Command::create_global_application_command(&ctx.http, |command| {
-            command
-                .name("get_vc_time")
-                .kind(CommandType::ChatInput)
-                .dm_permission(false)
-                .description("Get VC time of a user")
-                .create_option(|option| {
-                    option
-                        .name("user")
-                        .description("User that should be queried")
-                        .required(true)
-                        .kind(serenity::model::prelude::command::CommandOptionType::User)
-                })
-                .create_option(|option| {
-                    option
-                        .name("channel")
-                        .description("Channel that should be queried")
-                        .kind(serenity::model::prelude::command::CommandOptionType::Channel)
-                        .channel_types(&[ChannelType::Voice])
-                        .required(false)
-                })
-        })
-        .await
-        .unwrap();
-        Command::create_global_application_command(&ctx.http, |command| {
-            command
-                .name("leaderboard")
-                .kind(CommandType::ChatInput)
-                .dm_permission(false)
-                .description("Load the Leaderboard")
-                .create_option(|option| {
-                    option
-                        .name("channel")
-                        .description("Channel that the leaderboard should be for")
-                        .kind(serenity::model::prelude::command::CommandOptionType::Channel)
-                        .channel_types(&[ChannelType::Voice])
-                        .required(false)
-                })
-        })
-        .await
-        .unwrap();
-        println!("{} is connected!", ready.user.name);
+        commands::register(&ctx.http, &self.dev_guilds).await;
+        info!("{} is connected!", ready.user.name);
+    }
+    async fn guild_create(&self, _ctx: Context, guild: Guild, _is_new: bool) {
+        self.db.guild_available(guild.id);
+        for channel in guild.channels.values() {
+            if let Channel::Guild(channel) = channel {
+                self.update_channel(channel);
+            }
+        }
+    }
+    async fn guild_delete(
+        &self,
+        _ctx: Context,
+        incomplete: UnavailableGuild,
+        _full: Option<Guild>,
+    ) {
+        // Unavailable guilds are only affected by an outage, the bot is still a member
+        if !incomplete.unavailable {
+            self.db.guild_removed(incomplete.id);
+        }
     }
-    async fn voice_state_update(&self, _ctx: Context, new: VoiceState) {
+    async fn guild_member_addition(&self, _ctx: Context, member: Member) {
+        self.db.member_added(member.guild_id, member.user.id);
+    }
+    async fn guild_member_removal(
+        &self,
+        _ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        _member: Option<Member>,
+    ) {
+        self.db.member_removed(guild_id, user.id);
+    }
+    async fn channel_delete(&self, _ctx: Context, channel: &GuildChannel) {
+        if matches!(channel.kind, ChannelType::Voice | ChannelType::Stage) {
+            self.db
+                .delete_channel(channel.guild_id, channel.id, channel.name.clone());
+        }
+    }
+    async fn channel_update(&self, _ctx: Context, _old: Option<Channel>, new: Channel) {
+        if let Channel::Guild(channel) = new {
+            self.update_channel(&channel);
+        }
+    }
+    async fn voice_state_update(&self, ctx: Context, _old: Option<VoiceState>, new: VoiceState) {
+        // Bots like music players would sit at the top of every leaderboard
+        if new.member.as_ref().map(|m| m.user.bot).unwrap_or(false) {
+            return;
+        }
+        let channel = new.channel_id.and_then(|c| ctx.cache.guild_channel(c));
+        // The category might have changed while the bot was offline
+        if let Some(channel) = &channel {
+            self.update_channel(channel);
+        }
+        // Without the members intent, members that were noticed to have left are back
+        // once they show up in voice
+        if let Some(guild_id) = new
+            .guild_id
+            .filter(|_| !self.features.members && new.channel_id.is_some())
+        {
+            self.db.member_added(guild_id, new.user_id);
+        }
+        let roles = new.member.map(|m| m.roles).unwrap_or_default();
+        // Members are also suppressed in the AFK channel or without the permission to speak,
+        // only in stage channels it means listening in the audience. Members that raised their
+        // hand with `request_to_speak_timestamp` still listen until they are invited
+        let stage = channel.is_some_and(|channel| channel.kind == ChannelType::Stage);
+        let audience = stage && new.suppress;
         self.db
-            .update_voicestate(new.user_id, new.channel_id, new.guild_id)
+            .update_voicestate(new.user_id, new.channel_id, new.guild_id, roles, audience)
     }
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
         match interaction {
             Interaction::Ping(_) => {}
+            Interaction::ApplicationCommand(command)
+                if command.data.kind != CommandType::ChatInput =>
+            {
+                self.handle_context_menu(ctx, command)
+            }
             Interaction::ApplicationCommand(command) => match command.data.name.as_str() {
                 "opt_out" => {
                     self.db.add_excluded_user(command.user.id);
-                    command
-                        .create_interaction_response(&ctx.http, |response| {
-                            response
-                                .kind(InteractionResponseType::ChannelMessageWithSource)
-                                .interaction_response_data(|data| {
-                                    data.ephemeral(true).content(
-                                        "You are now opting out of voice channel data aggregation.",
-                                    )
-                                })
-                        })
-                        .await
-                        .unwrap();
+                    self.db.reply(Reply::OptedOut, ctx.http, command);
                 }
                 "opt_in" => {
                     self.db.remove_excluded_user(command.user.id);
-                    command
-                        .create_interaction_response(&ctx.http, |response| {
-                            response
-                                .kind(InteractionResponseType::ChannelMessageWithSource)
-                                .interaction_response_data(|data| {
-                                    data.ephemeral(true).content(
-                                        "You are now opting into voice channel data aggregation.",
-                                    )
-                                })
-                        })
-                        .await
-                        .unwrap();
+                    self.db.reply(Reply::OptedIn, ctx.http, command);
                 }
-                "get_vc_time" => {
-                    let args = &command.data.options;
-                    let channel = args.iter().find(|v| v.name == "channel").and_then(|v| {
-                        if let CommandDataOptionValue::Channel(channel) =
-                            v.resolved.as_ref().unwrap()
-                        {
-                            Some(channel.id)
-                        } else {
-                            None
-                        }
-                    });
-                    let user = args
-                        .iter()
-                        .find(|v| v.name == "user")
-                        .and_then(|v| {
-                            if let CommandDataOptionValue::User(user, _) =
-                                v.resolved.as_ref().unwrap()
-                            {
-                                Some(user.id)
-                            } else {
-                                None
-                            }
-                        })
-                        .unwrap();
-                    self.db.get_time(
-                        UserId(user.0),
+                "get_vc_time" => match self
+                    .query(command.guild_id.unwrap(), &command.data.options)
+                    .await
+                {
+                    Ok(Query {
+                        user: Some(user),
+                        channel,
+                        period,
+                    }) => self.db.get_time(
+                        user,
                         command.guild_id.unwrap(),
                         channel,
+                        period,
                         ctx.http,
                         command,
-                    );
-                }
+                    ),
+                    // Discord doesn't send the command without the required user
+                    Ok(_) => {}
+                    Err(reply) => self.db.reply(reply, ctx.http, command),
+                },
                 "leaderboard" => {
                     let args = &command.data.options;
-                    let channel = args.iter().find(|v| v.name == "channel").and_then(|v| {
-                        if let CommandDataOptionValue::Channel(channel) =
-                            v.resolved.as_ref().unwrap()
-                        {
-                            Some(channel.id)
-                        } else {
-                            None
-                        }
-                    });
-                    self.db
-                        .get_leaderboard(command.guild_id.unwrap(), channel, ctx.http, command);
+                    let format = match string_option(args, "format") {
+                        Some("image") => LeaderboardFormat::Image,
+                        _ => LeaderboardFormat::Text,
+                    };
+                    let limit = integer_option(args, "limit").unwrap_or(10) as usize;
+                    match self.query(command.guild_id.unwrap(), args).await {
+                        Ok(query) => self.db.get_leaderboard(
+                            command.guild_id.unwrap(),
+                            query.channel,
+                            query.period,
+                            format,
+                            limit,
+                            ctx.cache,
+                            ctx.http,
+                            command,
+                        ),
+                        Err(reply) => self.db.reply(reply, ctx.http, command),
+                    }
                 }
+                "rank" => match self
+                    .query(command.guild_id.unwrap(), &command.data.options)
+                    .await
+                {
+                    Ok(query) => self.db.get_rank(
+                        query.user.unwrap_or(command.user.id),
+                        command.guild_id.unwrap(),
+                        query.channel,
+                        query.period,
+                        ctx.cache,
+                        ctx.http,
+                        command,
+                    ),
+                    Err(reply) => self.db.reply(reply, ctx.http, command),
+                },
+                "heatmap" => match self
+                    .query(command.guild_id.unwrap(), &command.data.options)
+                    .await
+                {
+                    Ok(query) => self.db.get_heatmap(
+                        command.guild_id.unwrap(),
+                        query.user,
+                        query.channel,
+                        query.period,
+                        ctx.http,
+                        command,
+                    ),
+                    Err(reply) => self.db.reply(reply, ctx.http, command),
+                },
+                "settings" => self.handle_settings(ctx, command),
+                "admin" => self.handle_admin(ctx, command),
                 _ => {}
             },
             Interaction::MessageComponent(_) => todo!(),
-            Interaction::Autocomplete(_) => todo!(),
+            Interaction::Autocomplete(autocomplete) => {
+                let Some((focused, options)) = focused_option(&autocomplete.data.options) else {
+                    return;
+                };
+                let kind = match focused.name.as_str() {
+                    "channel" => AutocompleteKind::Channel,
+                    "user" => AutocompleteKind::User {
+                        channel_id: string_option(options, "channel")
+                            .and_then(parse_id)
+                            .map(ChannelId),
+                    },
+                    "period" => AutocompleteKind::Period,
+                    _ => return,
+                };
+                let typed = focused
+                    .value
+                    .as_ref()
+                    .and_then(|value| value.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                self.db
+                    .autocomplete(kind, typed, ctx.cache, ctx.http, autocomplete);
+            }
             Interaction::ModalSubmit(_) => todo!(),
         }
     }
 }
 
-pub async fn build_bot(token: &str, db: Arc<DbManager>) -> serenity::Result<()> {
+/// Builds the client, it still has to be started
+pub async fn build_bot(
+    token: &str,
+    db: Arc<DbManager>,
+    dev_guilds: Vec<GuildId>,
+    features: Features,
+) -> serenity::Result<Client> {
     // Set gateway intents, which decides what events the bot will be notified about
-    let intents = GatewayIntents::GUILD_VOICE_STATES;
-
-    let mut client = Client::builder(token, intents)
-        .event_handler(Handler::new(db))
-        .await?;
+    // Guilds are needed to have channel names in the cache
+    let mut intents = GatewayIntents::GUILDS | GatewayIntents::GUILD_VOICE_STATES;
+    // Guild members is privileged and has to be enabled for the application,
+    // without it members leaving are only noticed when looking them up fails
+    if features.members {
+        intents |= GatewayIntents::GUILD_MEMBERS;
+    }
 
-    client.start().await
+    Client::builder(token, intents)
+        .event_handler(Handler::new(db, dev_guilds, features))
+        .await
 }
@@ -0,0 +1,715 @@
+use std::collections::BTreeMap;
+
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandOption};
+use serenity::http::Http;
+use serenity::model::prelude::command::{
+    Command, CommandOption, CommandOptionChoice, CommandOptionType, CommandType,
+};
+use serenity::model::prelude::{CommandId, GuildId};
+use serenity::model::Permissions;
+use tracing::{error, info};
+
+use crate::format::TimeFormat;
+use crate::i18n::Language;
+
+/// Localized texts, keyed by Discord locale
+type Localizations = BTreeMap<String, String>;
+
+/// Everything Discord stores about a command that we set
+///
+/// Definitions are compared with the commands Discord already has,
+/// so only changed commands have to be sent
+#[derive(Debug, PartialEq)]
+pub struct CommandDefinition {
+    name: String,
+    name_localizations: Localizations,
+    description: String,
+    description_localizations: Localizations,
+    kind: CommandType,
+    dm_permission: bool,
+    default_member_permissions: Option<Permissions>,
+    options: Vec<OptionDefinition>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct OptionDefinition {
+    name: String,
+    name_localizations: Localizations,
+    description: String,
+    description_localizations: Localizations,
+    kind: CommandOptionType,
+    required: bool,
+    choices: Vec<ChoiceDefinition>,
+    min_value: Option<i64>,
+    max_value: Option<i64>,
+    autocomplete: bool,
+    options: Vec<OptionDefinition>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ChoiceDefinition {
+    name: String,
+    name_localizations: Localizations,
+    value: String,
+}
+
+/// Name localizations of a command or option in every [Language] that translates it
+fn name_localizations(name: &str) -> Localizations {
+    let mut localizations = Localizations::new();
+    for language in Language::ALL {
+        if let Some(localized) = language.catalog().name(name) {
+            for locale in language.discord_locales() {
+                localizations.insert(locale.to_string(), localized.to_string());
+            }
+        }
+    }
+    localizations
+}
+
+/// A text for every Discord locale
+fn localizations(text: impl Fn(Language) -> String) -> Localizations {
+    let mut localizations = Localizations::new();
+    for language in Language::ALL {
+        for locale in language.discord_locales() {
+            localizations.insert(locale.to_string(), text(language));
+        }
+    }
+    localizations
+}
+
+impl CommandDefinition {
+    /// A slash command with the description of its name from the catalogs
+    fn chat_input(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            name_localizations: name_localizations(name),
+            description: Language::English.catalog().description(name).to_string(),
+            description_localizations: localizations(|l| l.catalog().description(name).to_string()),
+            kind: CommandType::ChatInput,
+            dm_permission: false,
+            default_member_permissions: None,
+            options: Vec::new(),
+        }
+    }
+    /// A command in the context menu of users or messages, these have no description
+    fn context_menu(name: &str, kind: CommandType) -> Self {
+        Self {
+            name: name.to_string(),
+            name_localizations: name_localizations(name),
+            description: String::new(),
+            description_localizations: Localizations::new(),
+            kind,
+            dm_permission: false,
+            default_member_permissions: None,
+            options: Vec::new(),
+        }
+    }
+    fn permissions(mut self, permissions: Permissions) -> Self {
+        self.default_member_permissions = Some(permissions);
+        self
+    }
+    fn option(mut self, option: OptionDefinition) -> Self {
+        self.options.push(option);
+        self
+    }
+    /// Whether `command` is what Discord stores for this definition
+    fn matches(&self, command: &Command) -> bool {
+        let mut current = Self::from(command);
+        // Guild commands can't be used in DMs anyway, Discord doesn't report the permission
+        if command.guild_id.is_some() {
+            current.dm_permission = self.dm_permission;
+        }
+        *self == current
+    }
+    fn build<'a>(
+        &self,
+        command: &'a mut CreateApplicationCommand,
+    ) -> &'a mut CreateApplicationCommand {
+        command
+            .name(&self.name)
+            .kind(self.kind)
+            .dm_permission(self.dm_permission);
+        if !self.description.is_empty() {
+            command.description(&self.description);
+        }
+        for (locale, name) in self.name_localizations.iter() {
+            command.name_localized(locale, name);
+        }
+        for (locale, description) in self.description_localizations.iter() {
+            command.description_localized(locale, description);
+        }
+        if let Some(permissions) = self.default_member_permissions {
+            command.default_member_permissions(permissions);
+        }
+        for option in self.options.iter() {
+            command.add_option(option.build());
+        }
+        command
+    }
+}
+
+impl From<&Command> for CommandDefinition {
+    fn from(command: &Command) -> Self {
+        Self {
+            name: command.name.clone(),
+            name_localizations: command
+                .name_localizations
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            description: command.description.clone(),
+            description_localizations: command
+                .description_localizations
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            kind: command.kind,
+            dm_permission: command.dm_permission.unwrap_or(true),
+            default_member_permissions: command.default_member_permissions,
+            options: command.options.iter().map(OptionDefinition::from).collect(),
+        }
+    }
+}
+
+impl OptionDefinition {
+    /// An option of the command at `path`, e.g. `settings.timezone`
+    fn new(path: &str, name: &str, kind: CommandOptionType) -> Self {
+        let key = format!("{path}.{name}");
+        Self {
+            name: name.to_string(),
+            name_localizations: name_localizations(name),
+            description: Language::English.catalog().description(&key).to_string(),
+            description_localizations: localizations(|l| l.catalog().description(&key).to_string()),
+            kind,
+            required: false,
+            choices: Vec::new(),
+            min_value: None,
+            max_value: None,
+            autocomplete: false,
+            options: Vec::new(),
+        }
+    }
+    fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+    fn range(mut self, min: i64, max: i64) -> Self {
+        self.min_value = Some(min);
+        self.max_value = Some(max);
+        self
+    }
+    fn choice(mut self, value: &str, name: impl Fn(Language) -> String) -> Self {
+        self.choices.push(ChoiceDefinition {
+            name: name(Language::English),
+            name_localizations: localizations(name),
+            value: value.to_string(),
+        });
+        self
+    }
+    fn option(mut self, option: OptionDefinition) -> Self {
+        self.options.push(option);
+        self
+    }
+    fn build(&self) -> CreateApplicationCommandOption {
+        let mut option = CreateApplicationCommandOption::default();
+        option
+            .name(&self.name)
+            .description(&self.description)
+            .kind(self.kind)
+            .required(self.required);
+        for (locale, name) in self.name_localizations.iter() {
+            option.name_localized(locale, name);
+        }
+        for (locale, description) in self.description_localizations.iter() {
+            option.description_localized(locale, description);
+        }
+        for choice in self.choices.iter() {
+            option.add_string_choice_localized(
+                &choice.name,
+                &choice.value,
+                choice.name_localizations.iter(),
+            );
+        }
+        if let Some(min) = self.min_value {
+            option.min_int_value(min);
+        }
+        if let Some(max) = self.max_value {
+            option.max_int_value(max);
+        }
+        if self.autocomplete {
+            option.set_autocomplete(true);
+        }
+        for sub_option in self.options.iter() {
+            option.add_sub_option(sub_option.build());
+        }
+        option
+    }
+}
+
+impl From<&CommandOption> for OptionDefinition {
+    fn from(option: &CommandOption) -> Self {
+        Self {
+            name: option.name.clone(),
+            name_localizations: option
+                .name_localizations
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            description: option.description.clone(),
+            description_localizations: option
+                .description_localizations
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            kind: option.kind,
+            required: option.required,
+            choices: option.choices.iter().map(ChoiceDefinition::from).collect(),
+            min_value: option.min_value.as_ref().and_then(|v| v.as_i64()),
+            max_value: option.max_value.as_ref().and_then(|v| v.as_i64()),
+            autocomplete: option.autocomplete,
+            options: option.options.iter().map(OptionDefinition::from).collect(),
+        }
+    }
+}
+
+impl From<&CommandOptionChoice> for ChoiceDefinition {
+    fn from(choice: &CommandOptionChoice) -> Self {
+        Self {
+            name: choice.name.clone(),
+            name_localizations: choice
+                .name_localizations
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            value: choice
+                .value
+                .as_str()
+                .map(str::to_string)
+                .unwrap_or_else(|| choice.value.to_string()),
+        }
+    }
+}
+
+/// Name of the user and message context menu commands showing the voice time
+pub const VOICE_TIME_MENU: &str = "Voice time";
+
+/// Optional string option that is filled in by autocompletion
+///
+/// Used for users, channels and periods, so suggestions can be limited to recorded data
+fn autocompleted(path: &str, name: &str) -> OptionDefinition {
+    let mut option = OptionDefinition::new(path, name, CommandOptionType::String);
+    option.autocomplete = true;
+    option
+}
+
+/// All commands of the bot
+pub fn definitions() -> Vec<CommandDefinition> {
+    let time_formats = TimeFormat::ALL.into_iter().fold(
+        OptionDefinition::new("settings.time_format", "style", CommandOptionType::String)
+            .required(),
+        |option, time_format| {
+            option.choice(time_format.name(), |l| {
+                time_format.format(11525, l.catalog())
+            })
+        },
+    );
+    let languages = Language::ALL.into_iter().fold(
+        OptionDefinition::new("settings.language", "language", CommandOptionType::String)
+            .required()
+            .choice("auto", |l| l.catalog().automatic.to_string()),
+        |option, language| option.choice(language.code(), |_| language.native_name().to_string()),
+    );
+    vec![
+        CommandDefinition::chat_input("opt_out"),
+        CommandDefinition::chat_input("opt_in"),
+        CommandDefinition::chat_input("get_vc_time")
+            .option(autocompleted("get_vc_time", "user").required())
+            .option(autocompleted("get_vc_time", "channel"))
+            .option(autocompleted("get_vc_time", "period")),
+        CommandDefinition::chat_input("leaderboard")
+            .option(autocompleted("leaderboard", "channel"))
+            .option(autocompleted("leaderboard", "period"))
+            .option(
+                OptionDefinition::new("leaderboard", "format", CommandOptionType::String)
+                    .choice("text", |_| "text".to_string())
+                    .choice("image", |_| "image".to_string()),
+            )
+            .option(
+                OptionDefinition::new("leaderboard", "limit", CommandOptionType::Integer)
+                    .range(1, 25),
+            ),
+        CommandDefinition::chat_input("rank")
+            .option(autocompleted("rank", "user"))
+            .option(autocompleted("rank", "channel"))
+            .option(autocompleted("rank", "period")),
+        CommandDefinition::chat_input("heatmap")
+            .option(autocompleted("heatmap", "user"))
+            .option(autocompleted("heatmap", "channel"))
+            .option(autocompleted("heatmap", "period")),
+        CommandDefinition::chat_input("settings")
+            .permissions(Permissions::MANAGE_GUILD)
+            .option(
+                OptionDefinition::new("settings", "timezone", CommandOptionType::SubCommand)
+                    .option(
+                        OptionDefinition::new(
+                            "settings.timezone",
+                            "name",
+                            CommandOptionType::String,
+                        )
+                        .required(),
+                    ),
+            )
+            .option(
+                OptionDefinition::new("settings", "time_format", CommandOptionType::SubCommand)
+                    .option(time_formats),
+            )
+            .option(
+                OptionDefinition::new("settings", "language", CommandOptionType::SubCommand)
+                    .option(languages),
+            )
+            .option(
+                OptionDefinition::new("settings", "exclude_role", CommandOptionType::SubCommand)
+                    .option(
+                        OptionDefinition::new(
+                            "settings.exclude_role",
+                            "role",
+                            CommandOptionType::Role,
+                        )
+                        .required(),
+                    )
+                    .option(
+                        OptionDefinition::new(
+                            "settings.exclude_role",
+                            "mode",
+                            CommandOptionType::String,
+                        )
+                        .required()
+                        .choice("tracking", |l| l.catalog().exclusion_modes[0].to_string())
+                        .choice("leaderboard", |l| {
+                            l.catalog().exclusion_modes[1].to_string()
+                        })
+                        .choice("off", |l| l.catalog().exclusion_modes[2].to_string()),
+                    ),
+            )
+            .option(
+                OptionDefinition::new("settings", "archive_deleted", CommandOptionType::SubCommand)
+                    .option(
+                        OptionDefinition::new(
+                            "settings.archive_deleted",
+                            "enabled",
+                            CommandOptionType::Boolean,
+                        )
+                        .required(),
+                    ),
+            )
+            .option(
+                OptionDefinition::new("settings", "webhook", CommandOptionType::SubCommand)
+                    .option(OptionDefinition::new(
+                        "settings.webhook",
+                        "url",
+                        CommandOptionType::String,
+                    ))
+                    .option(OptionDefinition::new(
+                        "settings.webhook",
+                        "secret",
+                        CommandOptionType::String,
+                    )),
+            )
+            .option(
+                OptionDefinition::new("settings", "departed", CommandOptionType::SubCommand)
+                    .option(
+                        OptionDefinition::new(
+                            "settings.departed",
+                            "policy",
+                            CommandOptionType::String,
+                        )
+                        .required()
+                        .choice("keep", |l| l.catalog().departed_policies[0].to_string())
+                        .choice("hide", |l| l.catalog().departed_policies[1].to_string())
+                        .choice("delete", |l| l.catalog().departed_policies[2].to_string()),
+                    )
+                    .option(
+                        OptionDefinition::new(
+                            "settings.departed",
+                            "days",
+                            CommandOptionType::Integer,
+                        )
+                        .range(1, 365),
+                    ),
+            )
+            .option(
+                OptionDefinition::new("settings", "max_session", CommandOptionType::SubCommand)
+                    .option(
+                        OptionDefinition::new(
+                            "settings.max_session",
+                            "duration",
+                            CommandOptionType::String,
+                        )
+                        .required(),
+                    )
+                    .option(OptionDefinition::new(
+                        "settings.max_session",
+                        "close",
+                        CommandOptionType::Boolean,
+                    )),
+            ),
+        CommandDefinition::chat_input("admin")
+            .permissions(Permissions::MANAGE_GUILD)
+            .option(
+                OptionDefinition::new("admin", "adjust_time", CommandOptionType::SubCommand)
+                    .option(autocompleted("admin.adjust_time", "user").required())
+                    .option(autocompleted("admin.adjust_time", "channel").required())
+                    .option(
+                        OptionDefinition::new(
+                            "admin.adjust_time",
+                            "duration",
+                            CommandOptionType::String,
+                        )
+                        .required(),
+                    )
+                    .option(
+                        OptionDefinition::new(
+                            "admin.adjust_time",
+                            "reason",
+                            CommandOptionType::String,
+                        )
+                        .required(),
+                    ),
+            )
+            .option(
+                OptionDefinition::new("admin", "reset_user", CommandOptionType::SubCommand)
+                    .option(autocompleted("admin.reset_user", "user").required())
+                    .option(OptionDefinition::new(
+                        "admin.reset_user",
+                        "reason",
+                        CommandOptionType::String,
+                    )),
+            )
+            .option(
+                OptionDefinition::new("admin", "reset_channel", CommandOptionType::SubCommand)
+                    .option(autocompleted("admin.reset_channel", "channel").required())
+                    .option(OptionDefinition::new(
+                        "admin.reset_channel",
+                        "reason",
+                        CommandOptionType::String,
+                    )),
+            )
+            .option(
+                OptionDefinition::new("admin", "audit", CommandOptionType::SubCommand).option(
+                    OptionDefinition::new("admin.audit", "limit", CommandOptionType::Integer)
+                        .range(1, 25),
+                ),
+            )
+            .option(
+                OptionDefinition::new("admin", "flagged", CommandOptionType::SubCommand).option(
+                    OptionDefinition::new("admin.flagged", "limit", CommandOptionType::Integer)
+                        .range(1, 25),
+                ),
+            ),
+        CommandDefinition::context_menu(VOICE_TIME_MENU, CommandType::User),
+        CommandDefinition::context_menu(VOICE_TIME_MENU, CommandType::Message),
+    ]
+}
+
+/// Where commands are registered
+#[derive(Debug, Clone, Copy)]
+enum Scope {
+    Global,
+    /// Guild commands update instantly, which is useful while developing
+    Guild(GuildId),
+}
+
+impl Scope {
+    async fn get(self, http: &Http) -> serenity::Result<Vec<Command>> {
+        match self {
+            Self::Global => Command::get_global_application_commands_with_localizations(http).await,
+            Self::Guild(guild) => {
+                guild
+                    .get_application_commands_with_localizations(http)
+                    .await
+            }
+        }
+    }
+    async fn create(
+        self,
+        http: &Http,
+        definition: &CommandDefinition,
+    ) -> serenity::Result<Command> {
+        match self {
+            Self::Global => {
+                Command::create_global_application_command(http, |c| definition.build(c)).await
+            }
+            Self::Guild(guild) => {
+                guild
+                    .create_application_command(http, |c| definition.build(c))
+                    .await
+            }
+        }
+    }
+    async fn edit(
+        self,
+        http: &Http,
+        id: CommandId,
+        definition: &CommandDefinition,
+    ) -> serenity::Result<Command> {
+        match self {
+            Self::Global => {
+                Command::edit_global_application_command(http, id, |c| definition.build(c)).await
+            }
+            Self::Guild(guild) => {
+                guild
+                    .edit_application_command(http, id, |c| definition.build(c))
+                    .await
+            }
+        }
+    }
+    async fn delete(self, http: &Http, id: CommandId) -> serenity::Result<()> {
+        match self {
+            Self::Global => Command::delete_global_application_command(http, id).await,
+            Self::Guild(guild) => guild.delete_application_command(http, id).await,
+        }
+    }
+}
+
+/// Brings the commands Discord has in line with [definitions]
+///
+/// Only commands that changed are created, edited or deleted.
+/// If `dev_guilds` are given the commands are registered there instead of globally.
+pub async fn register(http: &Http, dev_guilds: &[GuildId]) {
+    let scopes: Vec<Scope> = if dev_guilds.is_empty() {
+        vec![Scope::Global]
+    } else {
+        dev_guilds
+            .iter()
+            .map(|guild| Scope::Guild(*guild))
+            .collect()
+    };
+    let definitions = definitions();
+    for scope in scopes {
+        let existing = match scope.get(http).await {
+            Ok(existing) => existing,
+            Err(err) => {
+                error!("Could not load {scope:?} commands: {err}");
+                continue;
+            }
+        };
+        for definition in definitions.iter() {
+            let current = existing
+                .iter()
+                .find(|c| c.name == definition.name && c.kind == definition.kind);
+            let result = match current {
+                Some(current) if definition.matches(current) => continue,
+                Some(current) => scope.edit(http, current.id, definition).await,
+                None => scope.create(http, definition).await,
+            };
+            match result {
+                Ok(_) => info!("Registered {scope:?} command {}", definition.name),
+                Err(err) => error!(
+                    "Could not register {scope:?} command {}: {err}",
+                    definition.name
+                ),
+            }
+        }
+        for command in existing.iter().filter(|c| {
+            !definitions
+                .iter()
+                .any(|d| d.name == c.name && d.kind == c.kind)
+        }) {
+            match scope.delete(http, command.id).await {
+                Ok(()) => info!("Deleted {scope:?} command {}", command.name),
+                Err(err) => error!("Could not delete {scope:?} command {}: {err}", command.name),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// The command as Discord stores it after registering `definition`
+    fn registered(definition: &CommandDefinition, guild: Option<u64>) -> Value {
+        let mut command = CreateApplicationCommand::default();
+        definition.build(&mut command);
+        let mut value = serde_json::to_value(command.0).unwrap();
+        value["id"] = json!("1");
+        value["application_id"] = json!("2");
+        value["version"] = json!("3");
+        // Context menu commands have an empty description
+        if value.get("description").is_none() {
+            value["description"] = json!("");
+        }
+        if let Some(guild) = guild {
+            value["guild_id"] = json!(guild.to_string());
+            value.as_object_mut().unwrap().remove("dm_permission");
+        }
+        value
+    }
+
+    fn command(value: Value) -> Command {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn definition(name: &str) -> CommandDefinition {
+        definitions().into_iter().find(|d| d.name == name).unwrap()
+    }
+
+    #[test]
+    fn unchanged_commands_match() {
+        for definition in definitions() {
+            let global = command(registered(&definition, None));
+            assert!(definition.matches(&global), "{}", definition.name);
+            // Discord leaves out the DM permission of guild commands
+            let guild = command(registered(&definition, Some(4)));
+            assert!(definition.matches(&guild), "{}", definition.name);
+        }
+    }
+
+    #[test]
+    fn changed_commands_differ() {
+        let definition = definition("leaderboard");
+        let mut value = registered(&definition, None);
+        value["description"] = json!("Old description");
+        assert!(!definition.matches(&command(value)));
+
+        let mut value = registered(&definition, None);
+        value["options"].as_array_mut().unwrap().pop();
+        assert!(!definition.matches(&command(value)));
+
+        let mut value = registered(&definition, None);
+        value["options"][0]["required"] = json!(true);
+        assert!(!definition.matches(&command(value)));
+
+        let mut value = registered(&definition, None);
+        value["dm_permission"] = json!(true);
+        assert!(!definition.matches(&command(value)));
+
+        let mut value = registered(&definition, None);
+        value["name_localizations"]
+            .as_object_mut()
+            .unwrap()
+            .insert("fr".to_string(), json!("classement"));
+        assert!(!definition.matches(&command(value)));
+    }
+
+    #[test]
+    fn changed_choices_differ() {
+        let definition = definition("settings");
+        let mut value = registered(&definition, None);
+        let time_format = value["options"]
+            .as_array_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|option| option["name"] == "time_format")
+            .unwrap();
+        time_format["options"][0]["choices"][0]["name"] = json!("Full");
+        assert!(!definition.matches(&command(value)));
+    }
+}
@@ -0,0 +1,395 @@
+//! Configuration from a TOML file, overridden by environment variables
+//!
+//! The file is taken from `--config <path>`, `CONFIG_PATH` or `voicetimebot.toml`
+//! if it exists. Without a file everything is read from the environment.
+//!
+//! ```toml
+//! discord_token = "..."            # DISCORD_TOKEN
+//! db_path = "voicetime.db"         # DB_PATH
+//! save_interval = "10m"            # SAVE_INTERVAL
+//! dev_guilds = [123456789]         # DEV_GUILDS, comma separated
+//!
+//! [control]
+//! address = "127.0.0.1:9500"       # CONTROL_ADDRESS
+//! token = "..."                    # CONTROL_TOKEN
+//! socket = "/run/voicetimebot.sock" # CONTROL_SOCKET
+//!
+//! [api]
+//! address = "127.0.0.1:8080"       # API_ADDRESS, the HTTP API is off without one
+//!
+//! [features]
+//! members = false     # needs the privileged guild members intent, see Features::members
+//! webhooks = true
+//! webhooks_allow_private = false   # http and local hosts, only for testing webhooks
+//! metrics = true      # /metrics on the HTTP API
+//! live_events = true  # /events on the HTTP API
+//!
+//! [defaults]          # settings of guilds that didn't configure them
+//! timezone = "Europe/Berlin"
+//! time_format = "short"
+//! language = "de"
+//! max_session = "12h"
+//! archive_deleted_channels = false
+//!
+//! [logging]
+//! level = "info,serenity=warn"     # LOG_LEVEL
+//! ```
+
+use std::{env, fs, net::SocketAddr, path::PathBuf, time::Duration};
+
+use serde::Deserialize;
+use serenity::model::prelude::GuildId;
+
+use crate::{db::GuildSettings, format::TimeFormat, i18n::Language};
+
+const DEFAULT_PATH: &str = "voicetimebot.toml";
+
+pub struct Config {
+    pub discord_token: String,
+    pub db_path: String,
+    pub save_interval: Duration,
+    /// Commands are registered in these guilds instead of globally
+    pub dev_guilds: Vec<GuildId>,
+    pub control: ControlConfig,
+    /// The HTTP API is only started if an address is configured
+    pub api_address: Option<SocketAddr>,
+    pub features: Features,
+    /// Settings of guilds that didn't configure them
+    pub defaults: GuildSettings,
+    /// Filter like `info` or `info,serenity=warn`
+    pub log_level: String,
+}
+
+pub struct ControlConfig {
+    pub address: SocketAddr,
+    /// Connections over TCP have to send `auth <token>` first, without a token TCP is off
+    pub token: Option<String>,
+    /// Unix socket protected by file permissions instead of the token
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    /// Requests the privileged guild members intent to notice members leaving right away
+    ///
+    /// It has to be enabled under Bot > Privileged Gateway Intents > Server Members Intent
+    /// in the developer portal first, otherwise Discord refuses the connection.
+    /// Without it members are only noticed to have left when looking them up fails,
+    /// which happens for the members shown on leaderboards and by `/rank`.
+    pub members: bool,
+    pub webhooks: bool,
+    /// Allows webhooks with `http` URLs and hosts in private networks or on this machine
+    ///
+    /// Meant for trying webhooks out against a local receiver, otherwise guild admins
+    /// could make the bot send requests to services that aren't reachable from outside.
+    pub webhooks_allow_private: bool,
+    pub metrics: bool,
+    pub live_events: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            members: false,
+            webhooks: true,
+            webhooks_allow_private: false,
+            metrics: true,
+            live_events: true,
+        }
+    }
+}
+
+/// The file as written, everything is optional so environment variables can fill in
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    discord_token: Option<String>,
+    db_path: Option<String>,
+    save_interval: Option<String>,
+    dev_guilds: Vec<u64>,
+    control: ControlFile,
+    api: ApiFile,
+    features: Features,
+    defaults: DefaultsFile,
+    logging: LoggingFile,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ControlFile {
+    address: Option<String>,
+    token: Option<String>,
+    socket: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ApiFile {
+    address: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct DefaultsFile {
+    timezone: Option<String>,
+    time_format: Option<String>,
+    language: Option<String>,
+    max_session: Option<String>,
+    archive_deleted_channels: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingFile {
+    level: Option<String>,
+}
+
+impl Config {
+    /// Reads the file and environment, returns every problem found instead of the first
+    pub fn load() -> Result<Config, Vec<String>> {
+        let mut file = match config_path() {
+            Some(path) => match fs::read_to_string(&path) {
+                Ok(text) => toml::from_str(&text)
+                    .map_err(|err| vec![format!("{}: {err}", path.display())])?,
+                Err(err) => return Err(vec![format!("{}: {err}", path.display())]),
+            },
+            None => File::default(),
+        };
+        override_from_env(&mut file.discord_token, "DISCORD_TOKEN");
+        override_from_env(&mut file.db_path, "DB_PATH");
+        override_from_env(&mut file.save_interval, "SAVE_INTERVAL");
+        override_from_env(&mut file.control.address, "CONTROL_ADDRESS");
+        override_from_env(&mut file.control.token, "CONTROL_TOKEN");
+        override_from_env(&mut file.control.socket, "CONTROL_SOCKET");
+        override_from_env(&mut file.api.address, "API_ADDRESS");
+        override_from_env(&mut file.logging.level, "LOG_LEVEL");
+
+        let mut errors = Vec::new();
+        if let Ok(guilds) = env::var("DEV_GUILDS") {
+            file.dev_guilds.clear();
+            for guild in guilds.split(',').map(str::trim).filter(|g| !g.is_empty()) {
+                match guild.parse() {
+                    Ok(guild) => file.dev_guilds.push(guild),
+                    Err(_) => errors.push(format!("DEV_GUILDS: {guild} is not a guild id")),
+                }
+            }
+        }
+        Self::from_file(file, errors)
+    }
+
+    /// Validates the file with the environment already applied, adding to `errors`
+    fn from_file(file: File, mut errors: Vec<String>) -> Result<Config, Vec<String>> {
+        let dev_guilds = file.dev_guilds.into_iter().map(GuildId).collect();
+        let discord_token = file.discord_token.filter(|t| !t.is_empty());
+        if discord_token.is_none() {
+            errors.push("discord_token or DISCORD_TOKEN has to be set".to_string());
+        }
+        let db_path = file.db_path.filter(|p| !p.is_empty());
+        if db_path.is_none() {
+            errors.push("db_path or DB_PATH has to be set".to_string());
+        }
+        let save_interval = parse_duration(file.save_interval, "save_interval", &mut errors)
+            .unwrap_or(Duration::from_secs(600));
+        let control_address = file.control.address.as_deref().unwrap_or("127.0.0.1:9500");
+        let control_address = parse_address(control_address, "control.address", &mut errors);
+        let api_address = file
+            .api
+            .address
+            .filter(|a| !a.is_empty())
+            .and_then(|address| parse_address(&address, "api.address", &mut errors));
+
+        let defaults = file.defaults;
+        let timezone = match defaults.timezone {
+            Some(name) => name.parse().unwrap_or_else(|_| {
+                errors.push(format!("defaults.timezone: unknown timezone {name}"));
+                Default::default()
+            }),
+            None => Default::default(),
+        };
+        let time_format = match defaults.time_format {
+            Some(name) => TimeFormat::from_name(&name).unwrap_or_else(|| {
+                errors.push(format!("defaults.time_format: unknown format {name}"));
+                Default::default()
+            }),
+            None => Default::default(),
+        };
+        let language = defaults.language.and_then(|code| {
+            let language = Language::from_code(&code);
+            if language.is_none() {
+                errors.push(format!("defaults.language: unknown language {code}"));
+            }
+            language
+        });
+        let max_session = parse_duration(defaults.max_session, "defaults.max_session", &mut errors)
+            .map(|max_session| max_session.as_secs());
+
+        let log_level = file.logging.level.unwrap_or_else(|| "info".to_string());
+        if let Err(err) = log_level.parse::<tracing_subscriber::EnvFilter>() {
+            errors.push(format!("logging.level: {err}"));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Config {
+            discord_token: discord_token.unwrap(),
+            db_path: db_path.unwrap(),
+            save_interval,
+            dev_guilds,
+            control: ControlConfig {
+                address: control_address.unwrap(),
+                token: file.control.token.filter(|t| !t.is_empty()),
+                socket: file
+                    .control
+                    .socket
+                    .filter(|s| !s.is_empty())
+                    .map(PathBuf::from),
+            },
+            api_address,
+            features: file.features,
+            defaults: GuildSettings::defaults(
+                timezone,
+                time_format,
+                language,
+                max_session,
+                defaults.archive_deleted_channels,
+            ),
+            log_level,
+        })
+    }
+}
+
+/// `--config <path>`, `CONFIG_PATH` or the default file if it exists
+fn config_path() -> Option<PathBuf> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+    }
+    if let Ok(path) = env::var("CONFIG_PATH") {
+        return Some(PathBuf::from(path));
+    }
+    Some(PathBuf::from(DEFAULT_PATH)).filter(|path| path.exists())
+}
+
+fn override_from_env(value: &mut Option<String>, name: &str) {
+    if let Ok(env_value) = env::var(name) {
+        *value = Some(env_value);
+    }
+}
+
+fn parse_address(address: &str, key: &str, errors: &mut Vec<String>) -> Option<SocketAddr> {
+    let parsed = address.parse().ok();
+    if parsed.is_none() {
+        errors.push(format!(
+            "{key}: expected an address like 127.0.0.1:9500, got {address}"
+        ));
+    }
+    parsed
+}
+
+/// Parses durations like `10m` or `1h 30m`, zero isn't allowed
+fn parse_duration(value: Option<String>, key: &str, errors: &mut Vec<String>) -> Option<Duration> {
+    let value = value?;
+    match humantime::parse_duration(&value) {
+        Ok(duration) if !duration.is_zero() => Some(duration),
+        _ => {
+            errors.push(format!("{key}: expected a duration like 10m, got {value}"));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(toml: &str) -> Result<Config, Vec<String>> {
+        Config::from_file(toml::from_str(toml).unwrap(), Vec::new())
+    }
+
+    #[test]
+    fn minimal() {
+        let config = validate("discord_token = \"token\"\ndb_path = \"voicetime.db\"").unwrap();
+        assert_eq!(config.save_interval, Duration::from_secs(600));
+        assert_eq!(config.control.address, "127.0.0.1:9500".parse().unwrap());
+        assert!(config.control.token.is_none());
+        assert!(config.api_address.is_none());
+        assert!(!config.features.members);
+        assert!(!config.features.webhooks_allow_private);
+        assert_eq!(config.log_level, "info");
+    }
+
+    #[test]
+    fn missing_required() {
+        let errors = validate("discord_token = \"\"").err().unwrap();
+        assert_eq!(
+            errors,
+            [
+                "discord_token or DISCORD_TOKEN has to be set",
+                "db_path or DB_PATH has to be set",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let errors = validate(
+            r#"
+            discord_token = "token"
+            db_path = "voicetime.db"
+            save_interval = "0s"
+
+            [control]
+            address = "localhost"
+
+            [api]
+            address = "127.0.0.1"
+
+            [defaults]
+            timezone = "Mars/Olympus_Mons"
+            time_format = "fortnights"
+            language = "xx"
+            max_session = "forever"
+
+            [logging]
+            level = "info,serenity=loud"
+            "#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            errors[..7],
+            [
+                "save_interval: expected a duration like 10m, got 0s",
+                "control.address: expected an address like 127.0.0.1:9500, got localhost",
+                "api.address: expected an address like 127.0.0.1:9500, got 127.0.0.1",
+                "defaults.timezone: unknown timezone Mars/Olympus_Mons",
+                "defaults.time_format: unknown format fortnights",
+                "defaults.language: unknown language xx",
+                "defaults.max_session: expected a duration like 10m, got forever",
+            ]
+            .map(String::from)[..]
+        );
+        assert!(errors[7].starts_with("logging.level: "));
+        assert_eq!(errors.len(), 8);
+    }
+
+    #[test]
+    fn errors_from_the_environment_are_kept() {
+        let errors = Config::from_file(
+            toml::from_str("discord_token = \"token\"\ndb_path = \"voicetime.db\"").unwrap(),
+            vec!["DEV_GUILDS: abc is not a guild id".to_string()],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(errors, ["DEV_GUILDS: abc is not a guild id"]);
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert!(toml::from_str::<File>("discord_tokn = \"token\"").is_err());
+        assert!(toml::from_str::<File>("[features]\nmetric = true").is_err());
+    }
+}
@@ -1,65 +1,503 @@
+//! Line based control protocol
+//!
+//! Every command is answered with `OK <details>` or `ERR <reason>`.
+//! Commands returning a list answer with `OK <count>` followed by one line per entry.
+//! The first command of a TCP connection has to be `auth <token>`,
+//! connections to the Unix socket are trusted as the socket is only accessible by its owner.
+
 use std::{
-    io::Read,
-    net::{Ipv4Addr, SocketAddrV4, TcpListener},
-    path::PathBuf,
-    sync::{mpsc, Arc},
-    thread,
-    time::Instant,
+    fs::{self, DirBuilder, Permissions},
+    io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time::Duration,
+};
+
+use serenity::model::prelude::UserId;
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+    net::{TcpListener, UnixListener},
+    sync::Notify,
 };
+use tracing::{error, info, warn};
+
+use crate::{config::ControlConfig, db::DbManager};
 
-use crate::{db::DbManager, SAVE_INTERVALL};
+/// Longest command accepted, so a line without end can't fill up the memory
+const MAX_LINE: usize = 1024;
+/// How long a TCP connection may take to authenticate before it is closed
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub fn create_control_server(port: u16, db: Arc<DbManager>, db_path: &str) {
+/// Starts the control server, TCP connections have to authenticate with the token
+///
+/// Without a token nobody could authenticate, so the server doesn't listen on TCP at all.
+/// If a socket path is given, the server also listens there without authentication.
+/// The DB is saved every `save_interval` either way, `stop` is notified by the `stop` command
+pub async fn create_control_server(
+    config: &ControlConfig,
+    save_interval: Duration,
+    db: Arc<DbManager>,
+    db_path: &str,
+    stop: Arc<Notify>,
+) -> io::Result<()> {
     let db_path = PathBuf::from(db_path);
-    let (sender, receiver) = mpsc::channel();
-    thread::spawn(move || {
-        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)).unwrap();
+    tokio::spawn(autosave(db.clone(), db_path.clone(), save_interval));
+    if let Some(socket) = &config.socket {
+        listen_on_socket(socket.clone(), db.clone(), db_path.clone(), stop.clone())?;
+    }
+    let Some(token) = &config.token else {
+        info!("No CONTROL_TOKEN set, the TCP control server is disabled");
+        return Ok(());
+    };
+    let token: Arc<str> = token.as_str().into();
+    let listener = TcpListener::bind(config.address).await?;
+    tokio::spawn(async move {
         loop {
-            if let Ok(connection) = listener.accept() {
-                let _ = sender.send(connection);
+            match listener.accept().await {
+                Ok((stream, address)) => {
+                    let (reader, writer) = stream.into_split();
+                    tokio::spawn(handle_connection(
+                        reader,
+                        writer,
+                        address.to_string(),
+                        Some(token.clone()),
+                        db.clone(),
+                        db_path.clone(),
+                        stop.clone(),
+                    ));
+                }
+                Err(err) => error!("{err}"),
             }
         }
     });
-    thread::spawn(move || {
-        let mut last_save = Instant::now();
-        let mut connections = Vec::new();
-        let mut buffer = [0u8; 1 << 16];
-        let mut dead_connections = vec![];
+    Ok(())
+}
+
+/// Listens on a Unix socket only the owner of the process can connect to
+fn listen_on_socket(
+    path: PathBuf,
+    db: Arc<DbManager>,
+    db_path: PathBuf,
+    stop: Arc<Notify>,
+) -> io::Result<()> {
+    // A socket left over from a previous run would make binding fail
+    remove_socket(&path)?;
+    let listener = bind_private(&path)?;
+    tokio::spawn(async move {
         loop {
-            if let Ok(connection) = receiver.try_recv() {
-                if let Ok(_) = connection.0.set_nonblocking(true) {
-                    connections.push(connection.0);
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let (reader, writer) = stream.into_split();
+                    tokio::spawn(handle_connection(
+                        reader,
+                        writer,
+                        path.display().to_string(),
+                        None,
+                        db.clone(),
+                        db_path.clone(),
+                        stop.clone(),
+                    ));
                 }
+                Err(err) => error!("{err}"),
             }
-            for (i, connection) in connections.iter_mut().enumerate() {
-                if let Ok(size) = connection.read(&mut buffer) {
-                    if size == 0 {
-                        dead_connections.push(i);
-                        continue;
-                    }
-                    if let Ok(command) = std::str::from_utf8(&buffer[0..size]).map(|v| v.trim()) {
-                        match command {
-                            "save" => db.save_db(db_path.clone()),
-                            "stop" => db.stop_and_save_db(db_path.clone()),
-                            "exit" => {
-                                connection
-                                    .shutdown(std::net::Shutdown::Both)
-                                    .unwrap_or_else(|err| eprintln!("{err}"));
-                                dead_connections.push(i)
-                            }
-                            _ => {}
-                        }
-                    }
-                }
+        }
+    });
+    Ok(())
+}
+
+/// Binds a socket at `path` nobody but the owner can connect to at any time
+///
+/// Bound at `path` directly, the socket would be open to whoever the umask allows until its
+/// permissions are set. So it is bound in a new directory only the owner can enter instead,
+/// and moved to `path` once its permissions are restricted.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let dir = path.with_file_name(format!(".{name}.{}", process::id()));
+    // Fails if the directory exists, so nobody else can have prepared it
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    let socket = dir.join("socket");
+    let listener = UnixListener::bind(&socket).and_then(|listener| {
+        fs::set_permissions(&socket, Permissions::from_mode(0o600))?;
+        fs::rename(&socket, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&socket);
+    fs::remove_dir(&dir)?;
+    listener
+}
+
+/// Removes the socket at `path`, anything else that is there is left alone
+pub fn remove_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path.display()),
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+async fn autosave(db: Arc<DbManager>, db_path: PathBuf, save_interval: Duration) {
+    let mut interval = tokio::time::interval(save_interval);
+    // The first tick completes immediately, right after the DB was loaded
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        db.save_db(db_path.clone());
+    }
+}
+
+/// What the connection should do after a response was sent
+enum After {
+    Continue,
+    Close,
+    /// Stop the bot the same way as on SIGTERM
+    Stop,
+}
+
+/// Handles the commands of one connection, one per line
+///
+/// With a `token` the first line has to be `auth <token>`, otherwise the connection is closed
+async fn handle_connection(
+    reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    address: String,
+    token: Option<Arc<str>>,
+    db: Arc<DbManager>,
+    db_path: PathBuf,
+    stop: Arc<Notify>,
+) {
+    let mut reader = BufReader::new(reader);
+    if let Some(token) = token {
+        let line = tokio::time::timeout(AUTH_TIMEOUT, read_line(&mut reader, MAX_LINE)).await;
+        let authenticated = match line {
+            Ok(Ok(Some(line))) => line
+                .trim()
+                .strip_prefix("auth ")
+                .map(|sent| same_token(&token, sent))
+                .unwrap_or(false),
+            _ => false,
+        };
+        if !authenticated {
+            warn!("Control connection from {address} failed to authenticate");
+            let _ = writer.write_all(b"ERR authentication failed\n").await;
+            return;
+        }
+        if writer.write_all(b"OK authenticated\n").await.is_err() {
+            return;
+        }
+    }
+    loop {
+        let line = match read_line(&mut reader, MAX_LINE).await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                error!("{err}");
+                let _ = writer.write_all(format!("ERR {err}\n").as_bytes()).await;
+                break;
             }
-            dead_connections.reverse();
-            for i in dead_connections.drain(..) {
-                connections.remove(i);
+        };
+        let (response, after) = handle_command(line.trim(), &db, &db_path).await;
+        if let Err(err) = writer.write_all(response.as_bytes()).await {
+            error!("{err}");
+            break;
+        }
+        match after {
+            After::Continue => {}
+            After::Close => break,
+            After::Stop => {
+                let _ = writer.shutdown().await;
+                stop.notify_one();
+                break;
             }
-            if last_save.elapsed().as_secs() > SAVE_INTERVALL {
-                last_save = Instant::now();
-                db.save_db(db_path.clone());
+        }
+    }
+}
+
+/// Reads a line without its line ending, `None` once the connection was closed
+///
+/// Lines longer than `max` bytes are an error instead of being read until the end
+pub async fn read_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    max: usize,
+) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    // Room for the line ending, longer lines still end up over the limit
+    reader
+        .take(max as u64 + 2)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    }
+    if line.len() > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line longer than {max} bytes"),
+        ));
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Runs a command and returns the response including the final newline
+async fn handle_command(command: &str, db: &DbManager, db_path: &Path) -> (String, After) {
+    let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+    let response = match name {
+        "save" => match db.save_db_checked(db_path.to_path_buf()).await {
+            Ok(()) => format!("OK saved to {}", db_path.display()),
+            Err(err) => format!("ERR {err}"),
+        },
+        // Shutting down the shards, closing the sessions and saving is left to main
+        "stop" => return ("OK stopping\n".to_string(), After::Stop),
+        "exit" => return ("OK bye\n".to_string(), After::Close),
+        "status" => {
+            let status = db.status().await;
+            let last_save = match status.last_save {
+                Some(time) => time.to_string(),
+                None => "never".to_string(),
+            };
+            format!(
+                "OK uptime={} guilds={} active_sessions={} last_save={}",
+                status.uptime.as_secs(),
+                status.guilds,
+                status.active_sessions,
+                last_save
+            )
+        }
+        "sessions" => {
+            let sessions = db.open_sessions().await;
+            let mut response = format!("OK {}", sessions.len());
+            for session in sessions {
+                response.push_str(&format!(
+                    "\n{} {} {} {}",
+                    session.user, session.guild, session.channel, session.duration
+                ));
             }
+            response
         }
-    });
+        "export" => {
+            let totals = db.export().await;
+            let mut response = format!("OK {}", totals.len());
+            for (user, guild, channel, seconds) in totals {
+                response.push_str(&format!("\n{user} {guild} {channel} {seconds}"));
+            }
+            response
+        }
+        "optout" => match argument.trim().split_once(' ') {
+            Some((action, user)) => match (action, user.trim().parse()) {
+                ("add", Ok(user)) => {
+                    db.add_excluded_user(UserId(user));
+                    format!("OK {user} opted out")
+                }
+                ("remove", Ok(user)) => {
+                    db.remove_excluded_user(UserId(user));
+                    format!("OK {user} opted in")
+                }
+                (_, Err(_)) => "ERR expected a user id".to_string(),
+                _ => "ERR expected optout add|remove <user id>".to_string(),
+            },
+            None => "ERR expected optout add|remove <user id>".to_string(),
+        },
+        "user" => match argument.trim().parse() {
+            Ok(user) => {
+                let totals = db.user_totals(UserId(user)).await;
+                let mut response = format!("OK {}", totals.len());
+                for (guild, channel, seconds) in totals {
+                    response.push_str(&format!("\n{guild} {channel} {seconds}"));
+                }
+                response
+            }
+            Err(_) => "ERR expected a user id".to_string(),
+        },
+        "" => "ERR empty command".to_string(),
+        _ => format!("ERR unknown command {name}"),
+    };
+    (response + "\n", After::Continue)
+}
+
+/// Compares tokens in constant time, so the token can't be guessed from response times
+fn same_token(expected: &str, sent: &str) -> bool {
+    expected.len() == sent.len()
+        && expected
+            .bytes()
+            .zip(sent.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::Features, db::GuildSettings};
+
+    use super::*;
+
+    /// Sends `input` over a connection and returns everything that was answered
+    async fn converse(token: Option<&str>, input: &str) -> String {
+        let db = Arc::new(DbManager::new(
+            GuildSettings::default(),
+            Features::default(),
+        ));
+        let mut output = Vec::new();
+        handle_connection(
+            input.as_bytes(),
+            &mut output,
+            "test".to_string(),
+            token.map(Arc::from),
+            db,
+            PathBuf::from("unused.db"),
+            Arc::new(Notify::new()),
+        )
+        .await;
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn compare_tokens() {
+        assert!(same_token("secret", "secret"));
+        assert!(!same_token("secret", "secreT"));
+        assert!(!same_token("secret", "secre"));
+        assert!(!same_token("secret", "secrets"));
+        assert!(!same_token("secret", ""));
+    }
+
+    #[tokio::test]
+    async fn authenticated() {
+        let output = converse(Some("secret"), "auth secret\r\nexit\n").await;
+        assert_eq!(output, "OK authenticated\nOK bye\n");
+    }
+
+    #[tokio::test]
+    async fn wrong_token() {
+        let output = converse(Some("secret"), "auth wrong\nexit\n").await;
+        assert_eq!(output, "ERR authentication failed\n");
+    }
+
+    #[tokio::test]
+    async fn command_before_auth() {
+        let output = converse(Some("secret"), "status\nauth secret\n").await;
+        assert_eq!(output, "ERR authentication failed\n");
+    }
+
+    #[tokio::test]
+    async fn closed_before_auth() {
+        assert_eq!(
+            converse(Some("secret"), "").await,
+            "ERR authentication failed\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn long_auth_line() {
+        let input = format!("auth {}\n", "a".repeat(MAX_LINE));
+        let output = converse(Some("secret"), &input).await;
+        assert_eq!(output, "ERR authentication failed\n");
+    }
+
+    #[tokio::test]
+    async fn socket_needs_no_auth() {
+        assert_eq!(converse(None, "exit\n").await, "OK bye\n");
+    }
+
+    #[tokio::test]
+    async fn read_lines() {
+        let mut reader = BufReader::new("first\r\nsecond\n\nlast".as_bytes());
+        assert_eq!(read_line(&mut reader, 8).await.unwrap().unwrap(), "first");
+        assert_eq!(read_line(&mut reader, 8).await.unwrap().unwrap(), "second");
+        assert_eq!(read_line(&mut reader, 8).await.unwrap().unwrap(), "");
+        assert_eq!(read_line(&mut reader, 8).await.unwrap().unwrap(), "last");
+        assert!(read_line(&mut reader, 8).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn read_long_line() {
+        // The line ending doesn't count towards the limit
+        let mut reader = BufReader::new("12345678\r\n123456789\n".as_bytes());
+        assert_eq!(
+            read_line(&mut reader, 8).await.unwrap().unwrap(),
+            "12345678"
+        );
+        let err = read_line(&mut reader, 8).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn read_invalid_utf8() {
+        let mut reader = BufReader::new(&b"\xff\n"[..]);
+        let err = read_line(&mut reader, 8).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn invalid_commands() {
+        let output = converse(
+            None,
+            "\nfrobnicate\noptout\noptout toggle 5\noptout add abc\nuser abc\n",
+        )
+        .await;
+        assert_eq!(
+            output,
+            "ERR empty command\n\
+             ERR unknown command frobnicate\n\
+             ERR expected optout add|remove <user id>\n\
+             ERR expected optout add|remove <user id>\n\
+             ERR expected a user id\n\
+             ERR expected a user id\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn list_responses() {
+        let output = converse(None, "optout add 5\r\nsessions\nuser 5\nexport\n").await;
+        assert_eq!(output, "OK 5 opted out\nOK 0\nOK 0\nOK 0\n");
+    }
+
+    #[tokio::test]
+    async fn exit_closes() {
+        assert_eq!(converse(None, "exit\nsessions\n").await, "OK bye\n");
+    }
+
+    #[tokio::test]
+    async fn long_command_closes() {
+        let input = format!("{}\nsessions\n", "a".repeat(MAX_LINE + 1));
+        let output = converse(None, &input).await;
+        assert_eq!(output, format!("ERR line longer than {MAX_LINE} bytes\n"));
+    }
+
+    #[tokio::test]
+    async fn stop_notifies_main() {
+        let db = Arc::new(DbManager::new(
+            GuildSettings::default(),
+            Features::default(),
+        ));
+        let stop = Arc::new(Notify::new());
+        let mut output = Vec::new();
+        handle_connection(
+            "stop\nsessions\n".as_bytes(),
+            &mut output,
+            "test".to_string(),
+            None,
+            db,
+            PathBuf::from("unused.db"),
+            stop.clone(),
+        )
+        .await;
+        assert_eq!(output, b"OK stopping\n");
+        // The permit stored by notify_one completes this right away
+        stop.notified().await;
+    }
 }
@@ -1,22 +1,45 @@
+use chrono::{Datelike, TimeZone, Timelike};
+use chrono_tz::Tz;
 use serenity::{
     builder::CreateEmbed,
-    http::Http,
+    cache::Cache,
+    http::{Http, HttpError},
     model::prelude::{
-        application_command::ApplicationCommandInteraction, ChannelId, GuildId,
-        InteractionApplicationCommandCallbackDataFlags, UserId,
+        application_command::ApplicationCommandInteraction, autocomplete::AutocompleteInteraction,
+        AttachmentType, ChannelId, GuildId, InteractionApplicationCommandCallbackDataFlags,
+        InteractionResponseType, Member, RoleId, UserId,
     },
     utils::MessageBuilder,
 };
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
-    fs::File,
-    io::{Read, Write},
-    path::PathBuf,
-    sync::{mpsc::Sender, Arc, Mutex},
+    fs::{self, File},
+    io::{BufWriter, Cursor, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::Ordering,
+        mpsc::{RecvTimeoutError, SendError, Sender},
+        Arc, Mutex,
+    },
     thread::{self, JoinHandle},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    runtime::Runtime,
+    sync::{broadcast, oneshot},
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::Features,
+    format::TimeFormat,
+    i18n::{fill, Catalog, Language, Reply},
+    metrics::METRICS,
+    period::Period,
+    render,
+    webhook::{self, Webhook, WebhookEvent, MILESTONE_HOURS},
 };
-use tokio::runtime::Runtime;
 
 #[derive(Debug, Default, Hash, PartialEq, PartialOrd, Ord, Eq, Clone, Copy)]
 pub struct Seconds(u64);
@@ -25,6 +48,471 @@ pub struct VoiceState {
     time: Instant,
     channel: ChannelId,
     guild: GuildId,
+    /// Listening in the audience of a stage channel instead of speaking
+    audience: bool,
+}
+
+/// How `/leaderboard` presents its result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaderboardFormat {
+    Text,
+    Image,
+}
+
+/// What members with an excluded role are left out of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoleExclusion {
+    /// Their voice time isn't recorded at all
+    Tracking,
+    /// Their voice time is recorded, but they don't show up on leaderboards
+    Leaderboard,
+}
+
+/// What happens to the data of members that left the guild
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DepartedPolicy {
+    /// They stay on leaderboards
+    #[default]
+    Keep,
+    /// They are left out of leaderboards until they rejoin
+    Hide,
+    /// They are hidden and their data is deleted if they don't rejoin within the days
+    Delete { days: u64 },
+}
+
+/// Option of a command an autocompletion is requested for
+pub enum AutocompleteKind {
+    Channel,
+    /// Users, restricted to a channel if one was already chosen
+    User {
+        channel_id: Option<ChannelId>,
+    },
+    Period,
+}
+
+/// Values [AutocompleteKind]s are completed with, best match first
+enum Suggestions {
+    Channels(Vec<(ChannelId, ChannelLabel, Seconds)>),
+    Users(Vec<(UserId, Seconds)>),
+    Periods(Vec<Period>),
+}
+
+/// Position of a user on the leaderboard
+pub struct Rank {
+    /// 1-based position
+    position: usize,
+    total: usize,
+    time: Seconds,
+    /// User directly above and how much time they have more
+    gap: Option<(UserId, Seconds)>,
+    /// Entries around the user including the user, with their position
+    neighbors: Vec<(usize, UserId, Seconds)>,
+}
+
+/// A finished voice session, kept for time based statistics
+#[derive(Clone, Copy)]
+pub struct Session {
+    user: UserId,
+    guild: GuildId,
+    channel: ChannelId,
+    /// Unix timestamp of the start of the session
+    start: u64,
+    duration: Seconds,
+    /// Spent in the audience of a stage channel
+    audience: bool,
+    /// Category the channel was in when the session ended
+    parent: Option<ChannelId>,
+}
+
+impl Session {
+    /// Writes the [Session] to a [Writer][Write]
+    /// `audience` is stored by the section it is in, `parent` in a section of its own
+    fn to_bytes(self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+        writer.write_all(&self.user.0.to_le_bytes())?;
+        writer.write_all(&self.guild.0.to_le_bytes())?;
+        writer.write_all(&self.channel.0.to_le_bytes())?;
+        writer.write_all(&self.start.to_le_bytes())?;
+        writer.write_all(&self.duration.0.to_le_bytes())
+    }
+    /// Reads a [Session] from a [Reader][Read]
+    fn from_bytes(reader: &mut dyn Read, audience: bool) -> Result<Session, std::io::Error> {
+        Ok(Self {
+            user: UserId(read_u64(reader)?),
+            guild: GuildId(read_u64(reader)?),
+            channel: ChannelId(read_u64(reader)?),
+            start: read_u64(reader)?,
+            duration: Seconds(read_u64(reader)?),
+            audience,
+            parent: None,
+        })
+    }
+}
+
+/// A session that was longer than the maximum of its guild
+///
+/// Only the maximum is credited, the rest waits for an admin to review it
+pub struct FlaggedSession {
+    /// The whole session as it happened
+    session: Session,
+    credited: Seconds,
+}
+
+/// What is known about a voice channel, kept for channels missing from the cache
+#[derive(Default, Clone)]
+pub struct ChannelInfo {
+    /// Category the channel was last seen in
+    parent: Option<ChannelId>,
+    guild: GuildId,
+    /// Last known name, shown once the channel is deleted
+    name: String,
+    deleted: bool,
+}
+
+impl ChannelInfo {
+    /// Writes the [ChannelInfo] to a [Writer][Write]
+    fn to_bytes(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+        // 0 means the channel isn't in a category
+        let parent = self.parent.map(|p| p.0).unwrap_or(0);
+        writer.write_all(&parent.to_le_bytes())?;
+        writer.write_all(&self.guild.0.to_le_bytes())?;
+        write_string(writer, &self.name)?;
+        writer.write_all(&(self.deleted as u64).to_le_bytes())
+    }
+    /// Reads the [ChannelInfo] from a [Reader][Read]
+    /// Fields missing from older files keep their default value
+    fn from_bytes(reader: &mut dyn Read) -> Result<ChannelInfo, std::io::Error> {
+        let mut info = Self::default();
+        if let Some(parent) = optional(read_u64(reader))? {
+            info.parent = Some(ChannelId(parent)).filter(|p| p.0 != 0);
+        }
+        if let Some(guild) = optional(read_u64(reader))? {
+            info.guild = GuildId(guild);
+        }
+        if let Some(name) = optional(read_string(reader))? {
+            info.name = name;
+        }
+        if let Some(deleted) = optional(read_u64(reader))? {
+            info.deleted = deleted != 0;
+        }
+        Ok(info)
+    }
+}
+
+/// State of the bot as reported by the `status` control command
+pub struct DbStatus {
+    pub uptime: Duration,
+    /// Guilds the bot is currently in
+    pub guilds: usize,
+    pub active_sessions: usize,
+    /// Unix timestamp of the last successful save
+    pub last_save: Option<u64>,
+}
+
+/// Statistics of one user, as served by the HTTP API
+pub struct UserStats {
+    pub seconds: u64,
+    /// The part of `seconds` spent in the audience of stage channels
+    pub audience_seconds: u64,
+    /// 1-based position on the leaderboard, [None] if the user isn't on it
+    pub position: Option<usize>,
+    pub total: usize,
+}
+
+/// Events buffered for subscribers that fall behind, older ones are dropped
+const EVENT_BUFFER: usize = 256;
+
+/// A change of the voice channel a user is in
+///
+/// `user` is [None] if the user opted out or is hidden from the leaderboard of the guild
+#[derive(Clone)]
+pub enum VoiceEvent {
+    Join {
+        guild: GuildId,
+        channel: ChannelId,
+        user: Option<UserId>,
+    },
+    Leave {
+        guild: GuildId,
+        channel: ChannelId,
+        user: Option<UserId>,
+    },
+    Move {
+        guild: GuildId,
+        from: ChannelId,
+        to: ChannelId,
+        user: Option<UserId>,
+    },
+}
+
+impl VoiceEvent {
+    pub fn guild(&self) -> GuildId {
+        match self {
+            Self::Join { guild, .. } | Self::Leave { guild, .. } | Self::Move { guild, .. } => {
+                *guild
+            }
+        }
+    }
+}
+
+/// A user in a voice channel right now, `user` is redacted like in [VoiceEvent]s
+pub struct LiveSession {
+    pub guild: GuildId,
+    pub channel: ChannelId,
+    pub user: Option<UserId>,
+    pub duration: u64,
+}
+
+/// A session that is still going on
+pub struct OpenSession {
+    pub user: UserId,
+    pub guild: GuildId,
+    pub channel: ChannelId,
+    /// Seconds since the session started
+    pub duration: u64,
+}
+
+/// Channel the time of deleted channels is merged into, if the guild wants that
+const ARCHIVED_CHANNEL: ChannelId = ChannelId(0);
+
+/// How a channel is shown in responses, deleted channels can't be mentioned anymore
+#[derive(Clone)]
+enum ChannelLabel {
+    Mention(ChannelId),
+    Deleted(String),
+    Archived,
+}
+
+impl ChannelLabel {
+    fn text(&self, catalog: &Catalog) -> String {
+        match self {
+            Self::Mention(channel) => format!("<#{}>", channel.0),
+            Self::Deleted(name) => fill(catalog.deleted_channel, &[("name", name)]),
+            Self::Archived => catalog.archived_channels.to_string(),
+        }
+    }
+    /// Plain name for places that don't render mentions, like autocompletions
+    fn name(&self, cache: &Cache, catalog: &Catalog) -> String {
+        match self {
+            Self::Mention(channel) => cache
+                .guild_channel(*channel)
+                .map(|c| format!("#{}", c.name))
+                .unwrap_or_else(|| channel.to_string()),
+            _ => self.text(catalog),
+        }
+    }
+}
+
+/// A correction of recorded voice times made with `/admin`
+#[derive(Clone, Copy)]
+pub enum AdminAction {
+    /// Adds the seconds to the total of the user in the channel, negative values remove time
+    AdjustTime {
+        user: UserId,
+        channel: ChannelId,
+        seconds: i64,
+    },
+    ResetUser {
+        user: UserId,
+    },
+    ResetChannel {
+        channel: ChannelId,
+    },
+}
+
+/// An [AdminAction] as it is kept in the audit log
+pub struct AuditEntry {
+    guild: GuildId,
+    admin: UserId,
+    /// Unix timestamp of the action
+    time: u64,
+    action: AdminAction,
+    reason: String,
+}
+
+impl AuditEntry {
+    /// Writes the [AuditEntry] to a [Writer][Write]
+    fn to_bytes(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+        writer.write_all(&self.guild.0.to_le_bytes())?;
+        writer.write_all(&self.admin.0.to_le_bytes())?;
+        writer.write_all(&self.time.to_le_bytes())?;
+        // Every action is stored as kind, user, channel and seconds, unused fields are 0
+        let (kind, user, channel, seconds) = match self.action {
+            AdminAction::AdjustTime {
+                user,
+                channel,
+                seconds,
+            } => (0u64, user.0, channel.0, seconds),
+            AdminAction::ResetUser { user } => (1, user.0, 0, 0),
+            AdminAction::ResetChannel { channel } => (2, 0, channel.0, 0),
+        };
+        writer.write_all(&kind.to_le_bytes())?;
+        writer.write_all(&user.to_le_bytes())?;
+        writer.write_all(&channel.to_le_bytes())?;
+        writer.write_all(&seconds.to_le_bytes())?;
+        write_string(writer, &self.reason)
+    }
+    /// Reads the [AuditEntry] from a [Reader][Read]
+    fn from_bytes(reader: &mut dyn Read) -> Result<AuditEntry, std::io::Error> {
+        let guild = GuildId(read_u64(reader)?);
+        let admin = UserId(read_u64(reader)?);
+        let time = read_u64(reader)?;
+        let kind = read_u64(reader)?;
+        let user = UserId(read_u64(reader)?);
+        let channel = ChannelId(read_u64(reader)?);
+        let seconds = read_u64(reader)? as i64;
+        let action = match kind {
+            0 => AdminAction::AdjustTime {
+                user,
+                channel,
+                seconds,
+            },
+            1 => AdminAction::ResetUser { user },
+            2 => AdminAction::ResetChannel { channel },
+            _ => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "unknown admin action",
+                ))
+            }
+        };
+        Ok(Self {
+            guild,
+            admin,
+            time,
+            action,
+            reason: read_string(reader)?,
+        })
+    }
+}
+
+/// Per guild settings, configured with `/settings`
+#[derive(Default, Clone)]
+pub struct GuildSettings {
+    timezone: Tz,
+    time_format: TimeFormat,
+    /// Overrides the locale of the interaction if set
+    language: Option<Language>,
+    /// Longer sessions only count up to this and get flagged
+    max_session: Option<Seconds>,
+    /// Whether sessions are closed as soon as they reach `max_session`
+    close_long_sessions: bool,
+    excluded_roles: HashMap<RoleId, RoleExclusion>,
+    /// Whether the time of deleted channels is merged into [ARCHIVED_CHANNEL]
+    archive_deleted_channels: bool,
+    departed_policy: DepartedPolicy,
+    webhook: Option<Webhook>,
+}
+
+impl GuildSettings {
+    /// Settings guilds start with, taken from the config
+    pub fn defaults(
+        timezone: Tz,
+        time_format: TimeFormat,
+        language: Option<Language>,
+        max_session: Option<u64>,
+        archive_deleted_channels: bool,
+    ) -> Self {
+        Self {
+            timezone,
+            time_format,
+            language,
+            max_session: max_session.map(Seconds),
+            archive_deleted_channels,
+            ..Self::default()
+        }
+    }
+    /// Whether one of the roles is excluded in the given way
+    fn excludes(&self, roles: &[RoleId], exclusion: RoleExclusion) -> bool {
+        roles
+            .iter()
+            .any(|role| self.excluded_roles.get(role) == Some(&exclusion))
+    }
+    /// [Catalog] the response to `command` should be written with
+    fn catalog(&self, command: &ApplicationCommandInteraction) -> &'static Catalog {
+        self.catalog_for(&command.locale, command.guild_locale.as_deref())
+    }
+    fn catalog_for(&self, locale: &str, guild_locale: Option<&str>) -> &'static Catalog {
+        Language::select(self.language, locale, guild_locale).catalog()
+    }
+    /// Writes the [GuildSettings] to a [Writer][Write]
+    fn to_bytes(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+        write_string(writer, self.timezone.name())?;
+        writer.write_all(&self.time_format.to_u64().to_le_bytes())?;
+        // 0 means no language is configured
+        let language = self.language.map(|l| l.to_u64() + 1).unwrap_or(0);
+        writer.write_all(&language.to_le_bytes())?;
+        // 0 means sessions are not limited
+        let max_session = self.max_session.unwrap_or_default();
+        writer.write_all(&max_session.0.to_le_bytes())?;
+        writer.write_all(&(self.close_long_sessions as u64).to_le_bytes())?;
+        writer.write_all(&(self.excluded_roles.len() as u64).to_le_bytes())?;
+        for (role, exclusion) in self.excluded_roles.iter() {
+            writer.write_all(&role.0.to_le_bytes())?;
+            let exclusion: u64 = match exclusion {
+                RoleExclusion::Tracking => 0,
+                RoleExclusion::Leaderboard => 1,
+            };
+            writer.write_all(&exclusion.to_le_bytes())?;
+        }
+        writer.write_all(&(self.archive_deleted_channels as u64).to_le_bytes())?;
+        let (policy, days): (u64, u64) = match self.departed_policy {
+            DepartedPolicy::Keep => (0, 0),
+            DepartedPolicy::Hide => (1, 0),
+            DepartedPolicy::Delete { days } => (2, days),
+        };
+        writer.write_all(&policy.to_le_bytes())?;
+        writer.write_all(&days.to_le_bytes())?;
+        // An empty url means no webhook is configured
+        let (url, secret) = match &self.webhook {
+            Some(webhook) => (webhook.url.as_str(), webhook.secret.as_str()),
+            None => ("", ""),
+        };
+        write_string(writer, url)?;
+        write_string(writer, secret)
+    }
+    /// Reads the [GuildSettings] from a [Reader][Read]
+    /// Settings missing from older files keep their default value
+    fn from_bytes(reader: &mut dyn Read) -> Result<GuildSettings, std::io::Error> {
+        let mut settings = Self::default();
+        if let Some(timezone) = optional(read_string(reader))? {
+            settings.timezone = timezone.parse().unwrap_or_default();
+        }
+        if let Some(time_format) = optional(read_u64(reader))? {
+            settings.time_format = TimeFormat::from_u64(time_format).unwrap_or_default();
+        }
+        if let Some(language) = optional(read_u64(reader))? {
+            settings.language = language.checked_sub(1).and_then(Language::from_u64);
+        }
+        if let Some(max_session) = optional(read_u64(reader))? {
+            settings.max_session = Some(Seconds(max_session)).filter(|max| max.0 > 0);
+        }
+        if let Some(close_long_sessions) = optional(read_u64(reader))? {
+            settings.close_long_sessions = close_long_sessions != 0;
+        }
+        for _ in 0..optional(read_u64(reader))?.unwrap_or(0) {
+            let role = RoleId(read_u64(reader)?);
+            let exclusion = match read_u64(reader)? {
+                0 => RoleExclusion::Tracking,
+                _ => RoleExclusion::Leaderboard,
+            };
+            settings.excluded_roles.insert(role, exclusion);
+        }
+        if let Some(archive_deleted_channels) = optional(read_u64(reader))? {
+            settings.archive_deleted_channels = archive_deleted_channels != 0;
+        }
+        if let Some(policy) = optional(read_u64(reader))? {
+            settings.departed_policy = match (policy, read_u64(reader)?) {
+                (1, _) => DepartedPolicy::Hide,
+                (2, days) => DepartedPolicy::Delete { days },
+                _ => DepartedPolicy::Keep,
+            };
+        }
+        if let Some(url) = optional(read_string(reader))? {
+            let secret = read_string(reader)?;
+            settings.webhook = Some(Webhook { url, secret }).filter(|w| !w.url.is_empty());
+        }
+        Ok(settings)
+    }
 }
 
 const SILENT_FLAG: InteractionApplicationCommandCallbackDataFlags =
@@ -33,7 +521,34 @@ const SILENT_FLAG: InteractionApplicationCommandCallbackDataFlags =
 pub struct Db {
     excluded_users: HashSet<UserId>,
     voice_times: HashMap<UserId, HashMap<(GuildId, ChannelId), Seconds>>,
+    /// The part of `voice_times` spent in the audience of stage channels
+    audience_times: HashMap<UserId, HashMap<(GuildId, ChannelId), Seconds>>,
     voice_states: HashMap<UserId, VoiceState>,
+    sessions: Vec<Session>,
+    guild_settings: HashMap<GuildId, GuildSettings>,
+    /// Admin actions of all guilds, oldest first
+    audit_log: Vec<AuditEntry>,
+    flagged_sessions: Vec<FlaggedSession>,
+    /// Members that had a role excluded from leaderboards when they were last seen in voice
+    hidden_members: HashSet<(GuildId, UserId)>,
+    channel_info: HashMap<ChannelId, ChannelInfo>,
+    /// Guilds the bot was removed from with the unix timestamp it happened at
+    removed_guilds: HashMap<GuildId, u64>,
+    /// Members that left a guild with the unix timestamp they left at
+    departed_members: HashMap<(GuildId, UserId), u64>,
+    /// Guilds the bot is currently in, not saved
+    guilds: HashSet<GuildId>,
+    /// Where opted out users are, only kept for redacted [VoiceEvent]s and not saved
+    untracked_states: HashMap<UserId, (GuildId, ChannelId, Instant)>,
+    events: broadcast::Sender<VoiceEvent>,
+    /// Webhook events with the unix timestamp they happened at, waiting to be sent
+    webhook_events: Vec<(Webhook, WebhookEvent, u64)>,
+    /// Features enabled in the config
+    features: Features,
+    /// Settings of guilds that didn't configure them, from the config
+    default_settings: GuildSettings,
+    started: Instant,
+    last_save: Option<u64>,
 }
 
 impl Db {
@@ -42,7 +557,24 @@ impl Db {
         Self {
             excluded_users: HashSet::default(),
             voice_times: HashMap::default(),
+            audience_times: HashMap::default(),
             voice_states: HashMap::default(),
+            sessions: Vec::new(),
+            guild_settings: HashMap::default(),
+            audit_log: Vec::new(),
+            flagged_sessions: Vec::new(),
+            hidden_members: HashSet::default(),
+            channel_info: HashMap::default(),
+            removed_guilds: HashMap::default(),
+            departed_members: HashMap::default(),
+            guilds: HashSet::default(),
+            untracked_states: HashMap::default(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            webhook_events: Vec::new(),
+            features: Features::default(),
+            default_settings: GuildSettings::default(),
+            started: Instant::now(),
+            last_save: None,
         }
     }
     /// Writes the [Db] to a [Writer][Write]
@@ -62,6 +594,93 @@ impl Db {
                 writer.write_all(&time.0.to_le_bytes())?;
             }
         }
+        // Audience sessions are written to their own section at the end
+        let (audience_sessions, sessions): (Vec<&Session>, Vec<&Session>) =
+            self.sessions.iter().partition(|s| s.audience);
+        writer.write_all(&(sessions.len() as u64).to_le_bytes())?;
+        for session in sessions {
+            session.to_bytes(writer)?;
+        }
+        writer.write_all(&(self.guild_settings.len() as u64).to_le_bytes())?;
+        for (guild, settings) in self.guild_settings.iter() {
+            let mut buffer = Vec::new();
+            settings.to_bytes(&mut buffer)?;
+            writer.write_all(&guild.0.to_le_bytes())?;
+            writer.write_all(&(buffer.len() as u64).to_le_bytes())?;
+            writer.write_all(&buffer)?;
+        }
+        writer.write_all(&(self.audit_log.len() as u64).to_le_bytes())?;
+        for entry in self.audit_log.iter() {
+            entry.to_bytes(writer)?;
+        }
+        writer.write_all(&(self.flagged_sessions.len() as u64).to_le_bytes())?;
+        for flagged in self.flagged_sessions.iter() {
+            flagged.session.to_bytes(writer)?;
+            writer.write_all(&flagged.credited.0.to_le_bytes())?;
+        }
+        writer.write_all(&(self.hidden_members.len() as u64).to_le_bytes())?;
+        for (guild, user) in self.hidden_members.iter() {
+            writer.write_all(&guild.0.to_le_bytes())?;
+            writer.write_all(&user.0.to_le_bytes())?;
+        }
+        writer.write_all(&(audience_sessions.len() as u64).to_le_bytes())?;
+        for session in audience_sessions {
+            session.to_bytes(writer)?;
+        }
+        writer.write_all(&(self.audience_times.len() as u64).to_le_bytes())?;
+        for (user, times) in self.audience_times.iter() {
+            writer.write_all(&user.0.to_le_bytes())?;
+            writer.write_all(&(times.len() as u64).to_le_bytes())?;
+            for ((guild, channel), time) in times.iter() {
+                writer.write_all(&guild.0.to_le_bytes())?;
+                writer.write_all(&channel.0.to_le_bytes())?;
+                writer.write_all(&time.0.to_le_bytes())?;
+            }
+        }
+        writer.write_all(&(self.channel_info.len() as u64).to_le_bytes())?;
+        for (channel, info) in self.channel_info.iter() {
+            let mut buffer = Vec::new();
+            info.to_bytes(&mut buffer)?;
+            writer.write_all(&channel.0.to_le_bytes())?;
+            writer.write_all(&(buffer.len() as u64).to_le_bytes())?;
+            writer.write_all(&buffer)?;
+        }
+        writer.write_all(&(self.removed_guilds.len() as u64).to_le_bytes())?;
+        for (guild, removed) in self.removed_guilds.iter() {
+            writer.write_all(&guild.0.to_le_bytes())?;
+            writer.write_all(&removed.to_le_bytes())?;
+        }
+        writer.write_all(&(self.departed_members.len() as u64).to_le_bytes())?;
+        for ((guild, user), left) in self.departed_members.iter() {
+            writer.write_all(&guild.0.to_le_bytes())?;
+            writer.write_all(&user.0.to_le_bytes())?;
+            writer.write_all(&left.to_le_bytes())?;
+        }
+        // Flagged sessions keep their order, the audience ones are listed by index
+        let flagged_audience: Vec<u64> = self
+            .flagged_sessions
+            .iter()
+            .enumerate()
+            .filter(|(_, flagged)| flagged.session.audience)
+            .map(|(index, _)| index as u64)
+            .collect();
+        writer.write_all(&(flagged_audience.len() as u64).to_le_bytes())?;
+        for index in flagged_audience {
+            writer.write_all(&index.to_le_bytes())?;
+        }
+        // Categories of the sessions in the order they were written above, 0 means none
+        let parents: Vec<u64> = self
+            .sessions
+            .iter()
+            .filter(|s| !s.audience)
+            .chain(self.sessions.iter().filter(|s| s.audience))
+            .chain(self.flagged_sessions.iter().map(|f| &f.session))
+            .map(|s| s.parent.map(|p| p.0).unwrap_or(0))
+            .collect();
+        writer.write_all(&(parents.len() as u64).to_le_bytes())?;
+        for parent in parents {
+            writer.write_all(&parent.to_le_bytes())?;
+        }
         writer.flush()
     }
     /// Reads the [Db] from a [Reader][Read]
@@ -86,13 +705,119 @@ impl Db {
             }
             db.voice_times.insert(user_id, user_times);
         }
+        // Sections below were added later and are missing in older files
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            db.sessions.push(Session::from_bytes(reader, false)?);
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            let guild = GuildId(read_u64(reader)?);
+            let mut buffer = vec![0u8; read_u64(reader)? as usize];
+            reader.read_exact(&mut buffer)?;
+            let settings = GuildSettings::from_bytes(&mut Cursor::new(buffer))?;
+            db.guild_settings.insert(guild, settings);
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            db.audit_log.push(AuditEntry::from_bytes(reader)?);
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            db.flagged_sessions.push(FlaggedSession {
+                session: Session::from_bytes(reader, false)?,
+                credited: Seconds(read_u64(reader)?),
+            });
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            db.hidden_members
+                .insert((GuildId(read_u64(reader)?), UserId(read_u64(reader)?)));
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            db.sessions.push(Session::from_bytes(reader, true)?);
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            let user_id = UserId(read_u64(reader)?);
+            let mut user_times = HashMap::default();
+            for _ in 0..read_u64(reader)? {
+                user_times.insert(
+                    (GuildId(read_u64(reader)?), ChannelId(read_u64(reader)?)),
+                    Seconds(read_u64(reader)?),
+                );
+            }
+            db.audience_times.insert(user_id, user_times);
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            let channel = ChannelId(read_u64(reader)?);
+            let mut buffer = vec![0u8; read_u64(reader)? as usize];
+            reader.read_exact(&mut buffer)?;
+            let info = ChannelInfo::from_bytes(&mut Cursor::new(buffer))?;
+            db.channel_info.insert(channel, info);
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            db.removed_guilds
+                .insert(GuildId(read_u64(reader)?), read_u64(reader)?);
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            db.departed_members.insert(
+                (GuildId(read_u64(reader)?), UserId(read_u64(reader)?)),
+                read_u64(reader)?,
+            );
+        }
+        let len = optional(read_u64(reader))?.unwrap_or(0);
+        for _ in 0..len {
+            let index = read_u64(reader)? as usize;
+            if let Some(flagged) = db.flagged_sessions.get_mut(index) {
+                flagged.session.audience = true;
+            }
+        }
+        let mut sessions: Vec<&mut Session> = db
+            .sessions
+            .iter_mut()
+            .chain(db.flagged_sessions.iter_mut().map(|f| &mut f.session))
+            .collect();
+        match optional(read_u64(reader))? {
+            Some(len) => {
+                for index in 0..len as usize {
+                    let parent = Some(ChannelId(read_u64(reader)?)).filter(|p| p.0 != 0);
+                    if let Some(session) = sessions.get_mut(index) {
+                        session.parent = parent;
+                    }
+                }
+            }
+            // Older files didn't record the category, the current one is the best guess
+            None => {
+                for session in sessions {
+                    session.parent = db
+                        .channel_info
+                        .get(&session.channel)
+                        .and_then(|info| info.parent);
+                }
+            }
+        }
         Ok(db)
     }
-    fn get_time(&self, user: UserId, guild: GuildId, channel_id: Option<ChannelId>) -> Seconds {
+    fn get_time(
+        &self,
+        user: UserId,
+        guild: GuildId,
+        channel_id: Option<ChannelId>,
+        period: Period,
+    ) -> Seconds {
+        if let Some(range) = self.period_range(guild, period) {
+            let times = self.get_period_times(guild, channel_id, range);
+            return Seconds(times.get(&user).copied().unwrap_or(0));
+        }
         match self.voice_times.get(&user) {
             Some(data) => Seconds(
                 data.iter()
-                    .filter(|v| v.0 .0 == guild && channel_id.map(|c| c == v.0 .1).unwrap_or(true))
+                    .filter(|v| v.0 .0 == guild && self.in_channel(channel_id, v.0 .1))
                     .map(|v| v.1 .0)
                     .sum(),
             ),
@@ -103,24 +828,341 @@ impl Db {
         &self,
         guild: GuildId,
         channel_id: Option<ChannelId>,
+        period: Period,
     ) -> Vec<(UserId, Seconds)> {
         let mut leaderboard = Vec::new();
-        for (user, times) in self.voice_times.iter() {
-            let time = Seconds(
-                times
-                    .iter()
-                    .filter(|v| v.0 .0 == guild && channel_id.map(|c| c == v.0 .1).unwrap_or(true))
-                    .map(|v| v.1 .0)
-                    .sum(),
-            );
-            if time > Seconds(0) {
-                leaderboard.push((*user, time));
+        if let Some(range) = self.period_range(guild, period) {
+            for (user, time) in self.get_period_times(guild, channel_id, range) {
+                if time > 0 {
+                    leaderboard.push((user, Seconds(time)));
+                }
+            }
+        } else {
+            for (user, times) in self.voice_times.iter() {
+                let time = Seconds(
+                    times
+                        .iter()
+                        .filter(|v| v.0 .0 == guild && self.in_channel(channel_id, v.0 .1))
+                        .map(|v| v.1 .0)
+                        .sum(),
+                );
+                if time > Seconds(0) {
+                    leaderboard.push((*user, time));
+                }
             }
         }
+        let hide_departed = self.settings(guild).departed_policy != DepartedPolicy::Keep;
+        leaderboard.retain(|(user, _)| {
+            let departed = self.departed_members.contains_key(&(guild, *user));
+            !(self.hidden_members.contains(&(guild, *user)) || hide_departed && departed)
+        });
         leaderboard.sort_unstable_by_key(|value| value.1);
         leaderboard.reverse();
         leaderboard
     }
+    /// The part of [get_time][Self::get_time] spent in the audience of stage channels
+    fn get_audience_time(
+        &self,
+        user: UserId,
+        guild: GuildId,
+        channel_id: Option<ChannelId>,
+        period: Period,
+    ) -> Seconds {
+        if let Some((since, until)) = self.period_range(guild, period) {
+            return Seconds(
+                self.all_sessions()
+                    .filter(|s| s.audience && s.user == user && s.guild == guild)
+                    .filter(|s| Self::in_session(channel_id, s))
+                    .map(|s| {
+                        let end = (s.start + s.duration.0).min(until);
+                        end.saturating_sub(s.start.max(since))
+                    })
+                    .sum(),
+            );
+        }
+        Seconds(
+            self.audience_times
+                .get(&user)
+                .into_iter()
+                .flatten()
+                .filter(|((g, c), _)| *g == guild && self.in_channel(channel_id, *c))
+                .map(|(_, time)| time.0)
+                .sum(),
+        )
+    }
+    /// Position of the user on the leaderboard with the two entries above and below
+    fn get_rank(
+        &self,
+        user: UserId,
+        guild: GuildId,
+        channel_id: Option<ChannelId>,
+        period: Period,
+    ) -> Option<Rank> {
+        let leaderboard = self.get_leaderboard(guild, channel_id, period);
+        let index = leaderboard.iter().position(|entry| entry.0 == user)?;
+        let time = leaderboard[index].1;
+        let gap = index.checked_sub(1).map(|above| {
+            (
+                leaderboard[above].0,
+                Seconds(leaderboard[above].1 .0 - time.0),
+            )
+        });
+        let neighbors = leaderboard
+            .iter()
+            .enumerate()
+            .skip(index.saturating_sub(2))
+            .take(5.min(index + 3))
+            .map(|(i, (user, time))| (i + 1, *user, *time))
+            .collect();
+        Some(Rank {
+            position: index + 1,
+            total: leaderboard.len(),
+            time,
+            gap,
+            neighbors,
+        })
+    }
+    fn channel_label(&self, channel: ChannelId) -> ChannelLabel {
+        match self.channel_info.get(&channel) {
+            _ if channel == ARCHIVED_CHANNEL => ChannelLabel::Archived,
+            Some(info) if info.deleted => ChannelLabel::Deleted(info.name.clone()),
+            _ => ChannelLabel::Mention(channel),
+        }
+    }
+    /// Marks a channel as deleted, its time is merged into [ARCHIVED_CHANNEL] if the guild wants
+    fn delete_channel(&mut self, guild: GuildId, channel: ChannelId, name: String) {
+        if !self.settings(guild).archive_deleted_channels {
+            let info = self.channel_info.entry(channel).or_default();
+            info.guild = guild;
+            info.name = name;
+            info.deleted = true;
+            return;
+        }
+        for times in self
+            .voice_times
+            .values_mut()
+            .chain(self.audience_times.values_mut())
+        {
+            if let Some(time) = times.remove(&(guild, channel)) {
+                times.entry((guild, ARCHIVED_CHANNEL)).or_default().0 += time.0;
+            }
+        }
+        let sessions = self
+            .sessions
+            .iter_mut()
+            .chain(self.flagged_sessions.iter_mut().map(|f| &mut f.session));
+        for session in sessions {
+            if session.guild == guild && session.channel == channel {
+                session.channel = ARCHIVED_CHANNEL;
+                session.parent = None;
+            }
+        }
+        self.channel_info.remove(&channel);
+    }
+    /// Writes the [Db] to the file at `path`
+    ///
+    /// The data is written to a temporary file first which then replaces the old one,
+    /// so a save that is interrupted leaves the last complete save behind
+    fn save(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        let started = Instant::now();
+        let temporary = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        self.to_bytes(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&temporary, path)?;
+        self.last_save = Some(unix_now());
+        METRICS
+            .save_duration_micros
+            .store(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+        METRICS.last_save.store(unix_now(), Ordering::Relaxed);
+        info!("Saved DB");
+        Ok(())
+    }
+    /// Deletes everything recorded for a member of a guild
+    fn purge_member(&mut self, guild: GuildId, user: UserId) {
+        for times in [&mut self.voice_times, &mut self.audience_times] {
+            if let Some(times) = times.get_mut(&user) {
+                times.retain(|(time_guild, _), _| *time_guild != guild);
+            }
+        }
+        self.sessions
+            .retain(|s| !(s.guild == guild && s.user == user));
+        self.flagged_sessions
+            .retain(|f| !(f.session.guild == guild && f.session.user == user));
+        self.hidden_members.remove(&(guild, user));
+        self.departed_members.remove(&(guild, user));
+    }
+    /// Deletes everything recorded for a guild
+    fn purge_guild(&mut self, guild: GuildId) {
+        for times in self
+            .voice_times
+            .values_mut()
+            .chain(self.audience_times.values_mut())
+        {
+            times.retain(|(time_guild, _), _| *time_guild != guild);
+        }
+        self.voice_times.retain(|_, times| !times.is_empty());
+        self.audience_times.retain(|_, times| !times.is_empty());
+        self.voice_states.retain(|_, state| state.guild != guild);
+        self.sessions.retain(|s| s.guild != guild);
+        self.flagged_sessions.retain(|f| f.session.guild != guild);
+        self.audit_log.retain(|entry| entry.guild != guild);
+        self.hidden_members
+            .retain(|(hidden_guild, _)| *hidden_guild != guild);
+        self.channel_info.retain(|_, info| info.guild != guild);
+        self.guild_settings.remove(&guild);
+        self.removed_guilds.remove(&guild);
+        self.departed_members
+            .retain(|(departed_guild, _), _| *departed_guild != guild);
+        info!("Purged data of guild {guild}");
+    }
+    /// Whether `channel` matches the filter of a query, which can also be a category
+    ///
+    /// Totals are only kept per channel, so they count for the category the channel is in now
+    fn in_channel(&self, filter: Option<ChannelId>, channel: ChannelId) -> bool {
+        match filter {
+            Some(filter) => filter == channel || self.parent(channel) == Some(filter),
+            None => true,
+        }
+    }
+    /// Like [in_channel][Self::in_channel], with the category the channel was in back then
+    fn in_session(filter: Option<ChannelId>, session: &Session) -> bool {
+        match filter {
+            Some(filter) => filter == session.channel || session.parent == Some(filter),
+            None => true,
+        }
+    }
+    /// Category the channel is in now
+    fn parent(&self, channel: ChannelId) -> Option<ChannelId> {
+        self.channel_info.get(&channel).and_then(|info| info.parent)
+    }
+    /// Channels and categories of the guild with their total time, most active first
+    fn get_channel_activity(&self, guild: GuildId) -> Vec<(ChannelId, Seconds)> {
+        let mut activity: HashMap<ChannelId, u64> = HashMap::new();
+        for times in self.voice_times.values() {
+            for ((time_guild, channel), time) in times.iter() {
+                if *time_guild == guild {
+                    *activity.entry(*channel).or_default() += time.0;
+                    if let Some(parent) = self.channel_info.get(channel).and_then(|i| i.parent) {
+                        *activity.entry(parent).or_default() += time.0;
+                    }
+                }
+            }
+        }
+        let mut activity: Vec<_> = activity
+            .into_iter()
+            .map(|(channel, time)| (channel, Seconds(time)))
+            .collect();
+        activity.sort_unstable_by_key(|value| value.1);
+        activity.reverse();
+        activity
+    }
+    /// Calendar months with recorded sessions in the guild, newest first
+    fn get_active_months(&self, guild: GuildId) -> Vec<Period> {
+        let timezone = self.settings(guild).timezone;
+        let mut months: Vec<_> = self
+            .sessions
+            .iter()
+            .filter(|s| s.guild == guild)
+            .filter_map(|s| timezone.timestamp_opt(s.start as i64, 0).single())
+            .map(|time| (time.year(), time.month()))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        months.sort_unstable();
+        months.reverse();
+        months
+            .into_iter()
+            .map(|(year, month)| Period::Named { year, month })
+            .collect()
+    }
+    fn settings(&self, guild: GuildId) -> GuildSettings {
+        self.guild_settings
+            .get(&guild)
+            .cloned()
+            .unwrap_or_else(|| self.default_settings.clone())
+    }
+    /// Settings of the guild to change, starting from the defaults of the config
+    fn settings_mut(&mut self, guild: GuildId) -> &mut GuildSettings {
+        let defaults = &self.default_settings;
+        self.guild_settings
+            .entry(guild)
+            .or_insert_with(|| defaults.clone())
+    }
+    fn period_range(&self, guild: GuildId, period: Period) -> Option<(u64, u64)> {
+        period.range(self.settings(guild).timezone, unix_now())
+    }
+    /// All finished [Sessions][Session] followed by the open ones up to now
+    fn all_sessions(&self) -> impl Iterator<Item = Session> + '_ {
+        let now = unix_now();
+        let open_sessions = self.voice_states.iter().map(move |(user, state)| {
+            let duration = state.time.elapsed().as_secs();
+            Session {
+                user: *user,
+                guild: state.guild,
+                channel: state.channel,
+                start: now - duration,
+                duration: Seconds(duration),
+                audience: state.audience,
+                parent: self.parent(state.channel),
+            }
+        });
+        self.sessions.iter().copied().chain(open_sessions)
+    }
+    /// Sums up the voice time per user between the given unix timestamps
+    /// Sessions overlapping the range only count with the part inside of it
+    fn get_period_times(
+        &self,
+        guild: GuildId,
+        channel_id: Option<ChannelId>,
+        (since, until): (u64, u64),
+    ) -> HashMap<UserId, u64> {
+        let mut times = HashMap::new();
+        self.all_sessions()
+            .filter(|s| s.guild == guild && Self::in_session(channel_id, s))
+            .for_each(|s| {
+                let end = (s.start + s.duration.0).min(until);
+                *times.entry(s.user).or_default() += end.saturating_sub(s.start.max(since));
+            });
+        times
+    }
+    /// Sums up the voice time per weekday and hour in the timezone of the guild
+    /// Open sessions are included up to now
+    fn get_heatmap(
+        &self,
+        guild: GuildId,
+        user_id: Option<UserId>,
+        channel_id: Option<ChannelId>,
+        period: Period,
+    ) -> [[u64; 24]; 7] {
+        let timezone = self.settings(guild).timezone;
+        let (since, until) = self.period_range(guild, period).unwrap_or((0, u64::MAX));
+        let mut buckets = [[0u64; 24]; 7];
+        self.all_sessions()
+            .filter(|s| {
+                s.guild == guild
+                    && user_id.map(|u| u == s.user).unwrap_or(true)
+                    && Self::in_session(channel_id, s)
+            })
+            .for_each(|s| {
+                let end = (s.start + s.duration.0).min(until);
+                let mut cursor = s.start.max(since);
+                while cursor < end {
+                    let Some(time) = timezone.timestamp_opt(cursor as i64, 0).single() else {
+                        break;
+                    };
+                    let into_hour = (time.minute() * 60 + time.second()) as u64;
+                    let slice = (3600 - into_hour).min(end - cursor);
+                    buckets[time.weekday().num_days_from_monday() as usize]
+                        [time.hour() as usize] += slice;
+                    cursor += slice;
+                }
+            });
+        buckets
+    }
     fn add_time_to_user(
         &mut self,
         user_id: UserId,
@@ -135,156 +1177,988 @@ impl Db {
             .copied()
             .unwrap_or_default();
         user_time.0 += duration.as_secs();
-        println!("{}|{}|{:?}", user_id, channel_id, user_time);
+        debug!("{}|{}|{:?}", user_id, channel_id, user_time);
         user_time_map.insert((guild_id, channel_id), user_time);
     }
+    /// Applies an [AdminAction] and records it in the audit log
+    ///
+    /// Adjustments only change the totals, so they don't show up in periods or heatmaps
+    fn apply_admin_action(&mut self, entry: AuditEntry) {
+        let guild = entry.guild;
+        match entry.action {
+            AdminAction::AdjustTime {
+                user,
+                channel,
+                seconds,
+            } => {
+                let time = self
+                    .voice_times
+                    .entry(user)
+                    .or_default()
+                    .entry((guild, channel))
+                    .or_default();
+                time.0 = time.0.saturating_add_signed(seconds);
+            }
+            AdminAction::ResetUser { user } => {
+                for times in [&mut self.voice_times, &mut self.audience_times] {
+                    if let Some(times) = times.get_mut(&user) {
+                        times.retain(|(time_guild, _), _| *time_guild != guild);
+                    }
+                }
+                self.sessions
+                    .retain(|s| !(s.guild == guild && s.user == user));
+                // A session that is still open only counts from now on
+                if let Some(state) = self.voice_states.get_mut(&user) {
+                    if state.guild == guild {
+                        state.time = Instant::now();
+                    }
+                }
+            }
+            AdminAction::ResetChannel { channel } => {
+                for times in self
+                    .voice_times
+                    .values_mut()
+                    .chain(self.audience_times.values_mut())
+                {
+                    times.remove(&(guild, channel));
+                }
+                self.sessions
+                    .retain(|s| !(s.guild == guild && s.channel == channel));
+                for state in self.voice_states.values_mut() {
+                    if state.guild == guild && state.channel == channel {
+                        state.time = Instant::now();
+                    }
+                }
+            }
+        }
+        self.audit_log.push(entry);
+    }
     fn is_excluded_user(&self, user_id: &UserId) -> bool {
         self.excluded_users.contains(user_id)
     }
     fn shutdown(&mut self) {
         let voice_states: Vec<_> = self.voice_states.drain().collect();
         for (user_id, voice_state) in voice_states.into_iter() {
-            self.add_time_to_user(
-                UserId(user_id.0),
-                voice_state.guild,
-                voice_state.channel,
-                voice_state.time.elapsed(),
-            );
+            self.close_session(user_id, voice_state);
         }
     }
-    fn handle_voicestate(&mut self, user_id: UserId, voicestate: Option<VoiceState>) {
-        if self.is_excluded_user(&user_id) {
-            return;
-        }
-        let voicestate = if let Some(voicestate) = voicestate {
-            self.voice_states.insert(user_id, voicestate)
-        } else {
-            self.voice_states.remove(&user_id)
+    /// Credits the time of a [VoiceState] that ended now and records it as [Session]
+    /// Sessions longer than the maximum of the guild are capped and flagged
+    fn close_session(&mut self, user_id: UserId, voicestate: VoiceState) {
+        let duration = voicestate.time.elapsed().as_secs();
+        let session = Session {
+            user: user_id,
+            guild: voicestate.guild,
+            channel: voicestate.channel,
+            start: unix_now() - duration,
+            duration: Seconds(duration),
+            audience: voicestate.audience,
+            parent: self.parent(voicestate.channel),
         };
-        if let Some(voicestate) = voicestate {
-            self.add_time_to_user(
-                user_id,
-                voicestate.guild,
-                voicestate.channel,
-                voicestate.time.elapsed(),
-            );
+        let credited = match self.settings(voicestate.guild).max_session {
+            Some(max_session) if duration > max_session.0 => {
+                self.flagged_sessions.push(FlaggedSession {
+                    session,
+                    credited: max_session,
+                });
+                max_session
+            }
+            _ => session.duration,
+        };
+        self.sessions.push(Session {
+            duration: credited,
+            ..session
+        });
+        let before = self.guild_time(user_id, voicestate.guild);
+        self.add_time_to_user(
+            user_id,
+            voicestate.guild,
+            voicestate.channel,
+            Duration::from_secs(credited.0),
+        );
+        if !self.hidden_members.contains(&(voicestate.guild, user_id)) {
+            self.notify(WebhookEvent::SessionEnd {
+                guild: voicestate.guild,
+                channel: voicestate.channel,
+                user: user_id,
+                start: session.start,
+                duration: session.duration.0,
+                credited: credited.0,
+            });
+            let after = before + credited.0;
+            for hours in MILESTONE_HOURS {
+                if (before + 1..=after).contains(&(hours * 3600)) {
+                    self.notify(WebhookEvent::Milestone {
+                        guild: voicestate.guild,
+                        user: user_id,
+                        hours,
+                    });
+                }
+            }
+        }
+        if voicestate.audience {
+            let time = self
+                .audience_times
+                .entry(user_id)
+                .or_default()
+                .entry((voicestate.guild, voicestate.channel))
+                .or_default();
+            time.0 += credited.0;
+        }
+    }
+    /// Total seconds of the user in all channels of the guild
+    fn guild_time(&self, user: UserId, guild: GuildId) -> u64 {
+        self.voice_times
+            .get(&user)
+            .into_iter()
+            .flatten()
+            .filter(|((time_guild, _), _)| *time_guild == guild)
+            .map(|(_, time)| time.0)
+            .sum()
+    }
+    /// Queues the event for the webhook of its guild, if one is configured
+    fn notify(&mut self, event: WebhookEvent) {
+        let guild = match event {
+            WebhookEvent::SessionEnd { guild, .. }
+            | WebhookEvent::Milestone { guild, .. }
+            | WebhookEvent::OptOut { guild, .. } => guild,
+        };
+        if !self.features.webhooks {
+            return;
+        }
+        if let Some(webhook) = self.settings(guild).webhook {
+            self.webhook_events.push((webhook, event, unix_now()));
+        }
+    }
+    /// Tells every guild the user has time in that they opted out or in
+    fn notify_opt_out(&mut self, user: UserId, opted_out: bool) {
+        let guilds: HashSet<GuildId> = self
+            .voice_times
+            .get(&user)
+            .into_iter()
+            .flatten()
+            .map(|((guild, _), _)| *guild)
+            .collect();
+        for guild in guilds {
+            self.notify(WebhookEvent::OptOut {
+                guild,
+                user,
+                opted_out,
+            });
+        }
+    }
+    /// Closes open sessions that reached the maximum of a guild that wants them closed
+    fn sweep_sessions(&mut self) {
+        let expired: Vec<UserId> = self
+            .voice_states
+            .iter()
+            .filter(|(_, state)| {
+                let settings = self.settings(state.guild);
+                settings.close_long_sessions
+                    && settings
+                        .max_session
+                        .map(|max| state.time.elapsed().as_secs() >= max.0)
+                        .unwrap_or(false)
+            })
+            .map(|(user, _)| *user)
+            .collect();
+        for user_id in expired {
+            if let Some(voicestate) = self.voice_states.remove(&user_id) {
+                self.close_session(user_id, voicestate);
+            }
+        }
+        let now = unix_now();
+        let purged: Vec<GuildId> = self
+            .removed_guilds
+            .iter()
+            .filter(|(_, removed)| now.saturating_sub(**removed) >= GUILD_PURGE_DELAY)
+            .map(|(guild, _)| *guild)
+            .collect();
+        for guild in purged {
+            self.purge_guild(guild);
+        }
+        let purged: Vec<(GuildId, UserId)> = self
+            .departed_members
+            .iter()
+            .filter(
+                |((guild, _), left)| match self.settings(*guild).departed_policy {
+                    DepartedPolicy::Delete { days } => now.saturating_sub(**left) >= days * 86400,
+                    _ => false,
+                },
+            )
+            .map(|(member, _)| *member)
+            .collect();
+        for (guild, user) in purged {
+            self.purge_member(guild, user);
+        }
+    }
+    fn handle_voicestate(&mut self, user_id: UserId, voicestate: Option<VoiceState>) {
+        let current = voicestate
+            .as_ref()
+            .map(|state| (state.guild, state.channel));
+        if self.is_excluded_user(&user_id) {
+            let untracked = self.untracked_states.get(&user_id);
+            if current.is_some()
+                && untracked.map(|(guild, channel, _)| (*guild, *channel)) == current
+            {
+                return;
+            }
+            let previous = match voicestate {
+                Some(state) => self
+                    .untracked_states
+                    .insert(user_id, (state.guild, state.channel, state.time)),
+                None => self.untracked_states.remove(&user_id),
+            };
+            let previous = previous.map(|(guild, channel, _)| (guild, channel));
+            self.send_events(user_id, previous, current);
+            return;
+        }
+        // Muting, deafening, video and streaming update the voice state as well,
+        // they must not split the session
+        if let (Some(new), Some(old)) = (&voicestate, self.voice_states.get(&user_id)) {
+            if (new.guild, new.channel, new.audience) == (old.guild, old.channel, old.audience) {
+                return;
+            }
+        }
+        let voicestate = if let Some(voicestate) = voicestate {
+            self.voice_states.insert(user_id, voicestate)
+        } else {
+            self.voice_states.remove(&user_id)
+        };
+        let previous = voicestate
+            .as_ref()
+            .map(|state| (state.guild, state.channel));
+        self.send_events(user_id, previous, current);
+        if let Some(voicestate) = voicestate {
+            self.close_session(user_id, voicestate);
+        }
+    }
+    /// Whether the user is left out of [VoiceEvent]s and [LiveSession]s of the guild
+    fn is_redacted(&self, guild: GuildId, user: UserId) -> bool {
+        self.is_excluded_user(&user) || self.hidden_members.contains(&(guild, user))
+    }
+    /// Tells subscribers about a user moving from `previous` to `current` channel
+    fn send_events(
+        &self,
+        user: UserId,
+        previous: Option<(GuildId, ChannelId)>,
+        current: Option<(GuildId, ChannelId)>,
+    ) {
+        let redact = |guild| (!self.is_redacted(guild, user)).then_some(user);
+        let events = match (previous, current) {
+            (Some((guild, from)), Some((to_guild, to))) if guild == to_guild && from != to => {
+                vec![VoiceEvent::Move {
+                    guild,
+                    from,
+                    to,
+                    user: redact(guild),
+                }]
+            }
+            (previous, current) if previous != current => {
+                let leave = previous.map(|(guild, channel)| VoiceEvent::Leave {
+                    guild,
+                    channel,
+                    user: redact(guild),
+                });
+                let join = current.map(|(guild, channel)| VoiceEvent::Join {
+                    guild,
+                    channel,
+                    user: redact(guild),
+                });
+                leave.into_iter().chain(join).collect()
+            }
+            _ => Vec::new(),
+        };
+        for event in events {
+            // Sending only fails if nobody is subscribed
+            let _ = self.events.send(event);
         }
     }
     fn handle_message(&mut self, message: DbMessage, tokio: &mut Runtime) {
         match message {
             DbMessage::AddUserToOptOut { user_id } => {
-                self.excluded_users.insert(user_id);
+                // Users opting out while in voice are credited up to now and not after
+                if let Some(voicestate) = self.voice_states.remove(&user_id) {
+                    let untracked = (voicestate.guild, voicestate.channel, voicestate.time);
+                    self.close_session(user_id, voicestate);
+                    self.untracked_states.insert(user_id, untracked);
+                }
+                if self.excluded_users.insert(user_id) {
+                    self.notify_opt_out(user_id, true);
+                }
             }
             DbMessage::RemoverUserToOptOut { user_id } => {
-                self.excluded_users.remove(&user_id);
+                if self.excluded_users.remove(&user_id) {
+                    self.notify_opt_out(user_id, false);
+                }
+                self.untracked_states.remove(&user_id);
             }
             DbMessage::UpdateVoicestate {
                 user_id,
                 channel_id,
                 guild_id,
+                roles,
+                audience,
                 time,
             } => {
+                METRICS.voice_state_events.fetch_add(1, Ordering::Relaxed);
                 let mut voicestate = None;
-                if let Some(channel_id) = channel_id {
-                    if let Some(guild_id) = guild_id {
+                if let Some(guild_id) = guild_id {
+                    let settings = self.settings(guild_id);
+                    if settings.excludes(&roles, RoleExclusion::Leaderboard) {
+                        self.hidden_members.insert((guild_id, user_id));
+                    } else {
+                        self.hidden_members.remove(&(guild_id, user_id));
+                    }
+                    // Members with a role excluded from tracking are handled as if they left
+                    if let Some(channel_id) =
+                        channel_id.filter(|_| !settings.excludes(&roles, RoleExclusion::Tracking))
+                    {
                         voicestate = Some(VoiceState {
                             time,
                             channel: channel_id,
                             guild: guild_id,
+                            audience,
                         });
                     }
                 };
                 self.handle_voicestate(user_id, voicestate);
             }
-            DbMessage::SaveDb { path } => {
-                let mut file = File::create(path).unwrap();
-                self.to_bytes(&mut file).unwrap();
-                println!("Saved DB");
+            DbMessage::SaveDb { path, reply } => {
+                let result = self.save(&path);
+                match reply {
+                    Some(reply) => {
+                        let _ = reply.send(result);
+                    }
+                    None => {
+                        if let Err(err) = result {
+                            error!("Saving the DB failed: {err}");
+                        }
+                    }
+                }
+            }
+            DbMessage::Status { reply } => {
+                let _ = reply.send(DbStatus {
+                    uptime: self.started.elapsed(),
+                    guilds: self.guilds.len(),
+                    active_sessions: self.voice_states.len(),
+                    last_save: self.last_save,
+                });
+            }
+            DbMessage::OpenSessions { reply } => {
+                let mut sessions: Vec<_> = self
+                    .voice_states
+                    .iter()
+                    .map(|(user, state)| OpenSession {
+                        user: *user,
+                        guild: state.guild,
+                        channel: state.channel,
+                        duration: state.time.elapsed().as_secs(),
+                    })
+                    .collect();
+                sessions.sort_unstable_by_key(|session| std::cmp::Reverse(session.duration));
+                let _ = reply.send(sessions);
+            }
+            DbMessage::LiveSessions { guild_id, reply } => {
+                let tracked = self
+                    .voice_states
+                    .iter()
+                    .map(|(user, state)| (*user, state.guild, state.channel, state.time));
+                let untracked = self
+                    .untracked_states
+                    .iter()
+                    .map(|(user, (guild, channel, time))| (*user, *guild, *channel, *time));
+                let mut sessions: Vec<_> = tracked
+                    .chain(untracked)
+                    .filter(|(_, guild, _, _)| guild_id.is_none_or(|g| g == *guild))
+                    .map(|(user, guild, channel, time)| LiveSession {
+                        guild,
+                        channel,
+                        user: (!self.is_redacted(guild, user)).then_some(user),
+                        duration: time.elapsed().as_secs(),
+                    })
+                    .collect();
+                sessions.sort_unstable_by_key(|session| (session.guild, session.channel));
+                let _ = reply.send(sessions);
+            }
+            DbMessage::Export { reply } => {
+                let mut totals: Vec<_> = self
+                    .voice_times
+                    .iter()
+                    .filter(|(user, _)| !self.is_excluded_user(user))
+                    .flat_map(|(user, times)| {
+                        times
+                            .iter()
+                            .map(|((guild, channel), time)| (*user, *guild, *channel, time.0))
+                    })
+                    .collect();
+                totals.sort_unstable();
+                let _ = reply.send(totals);
+            }
+            DbMessage::UserTotals { user_id, reply } => {
+                let mut totals: Vec<_> = self
+                    .voice_times
+                    .get(&user_id)
+                    .into_iter()
+                    .flatten()
+                    .map(|((guild, channel), time)| (*guild, *channel, time.0))
+                    .collect();
+                totals.sort_unstable();
+                let _ = reply.send(totals);
+            }
+            DbMessage::LeaderboardData {
+                guild_id,
+                channel_id,
+                period,
+                reply,
+            } => {
+                let leaderboard = self.guilds.contains(&guild_id).then(|| {
+                    let mut leaderboard = self.get_leaderboard(guild_id, channel_id, period);
+                    leaderboard.retain(|(user, _)| !self.is_excluded_user(user));
+                    leaderboard
+                        .into_iter()
+                        .map(|(user, time)| (user, time.0))
+                        .collect()
+                });
+                let _ = reply.send(leaderboard);
+            }
+            DbMessage::CheckOptions {
+                guild_id,
+                user_id,
+                channel_id,
+                reply,
+            } => {
+                let known_user = |user| {
+                    self.voice_times
+                        .get(&user)
+                        .is_some_and(|times| times.keys().any(|(guild, _)| *guild == guild_id))
+                };
+                let known_channel = |channel| {
+                    self.get_channel_activity(guild_id)
+                        .iter()
+                        .any(|(active, _)| *active == channel)
+                };
+                let result = if user_id.is_some_and(|user| !known_user(user)) {
+                    Err(Reply::UnknownUser)
+                } else if channel_id.is_some_and(|channel| !known_channel(channel)) {
+                    Err(Reply::UnknownChannel)
+                } else {
+                    Ok(())
+                };
+                let _ = reply.send(result);
+            }
+            DbMessage::UserStats {
+                user_id,
+                guild_id,
+                channel_id,
+                period,
+                reply,
+            } => {
+                let stats = (self.guilds.contains(&guild_id)
+                    && !self.is_excluded_user(&user_id)
+                    && !self.hidden_members.contains(&(guild_id, user_id)))
+                .then(|| {
+                    let mut leaderboard = self.get_leaderboard(guild_id, channel_id, period);
+                    leaderboard.retain(|(user, _)| !self.is_excluded_user(user));
+                    UserStats {
+                        seconds: self.get_time(user_id, guild_id, channel_id, period).0,
+                        audience_seconds: self
+                            .get_audience_time(user_id, guild_id, channel_id, period)
+                            .0,
+                        position: leaderboard
+                            .iter()
+                            .position(|entry| entry.0 == user_id)
+                            .map(|index| index + 1),
+                        total: leaderboard.len(),
+                    }
+                });
+                let _ = reply.send(stats);
             }
             DbMessage::GetTime {
                 user_id,
                 guild_id,
                 channel_id,
+                period,
                 http,
                 command,
             } => {
                 tokio.spawn(send_time_message(
                     user_id,
                     guild_id,
-                    channel_id,
+                    channel_id.map(|c| self.channel_label(c)),
+                    period,
+                    self.settings(guild_id),
                     http,
                     command,
-                    self.get_time(user_id, guild_id, channel_id),
+                    self.get_time(user_id, guild_id, channel_id, period),
+                    self.get_audience_time(user_id, guild_id, channel_id, period),
                 ));
             }
             DbMessage::GetLeaderboard {
                 guild_id,
                 channel_id,
+                period,
+                format,
+                limit,
+                cache,
                 http,
                 command,
+                db,
             } => {
+                let mut leaderboard = self.get_leaderboard(guild_id, channel_id, period);
+                leaderboard.truncate(limit);
                 tokio.spawn(send_leaderboard_message(
-                    channel_id,
+                    guild_id,
+                    channel_id.map(|c| self.channel_label(c)),
+                    period,
+                    format,
+                    self.settings(guild_id),
+                    cache,
+                    http,
+                    command,
+                    leaderboard,
+                    db,
+                    // The members intent reports members leaving, otherwise they are looked up
+                    !self.features.members,
+                ));
+            }
+            DbMessage::GetHeatmap {
+                guild_id,
+                user_id,
+                channel_id,
+                period,
+                http,
+                command,
+            } => {
+                tokio.spawn(send_heatmap_message(
+                    period,
+                    self.settings(guild_id),
+                    http,
+                    command,
+                    self.get_heatmap(guild_id, user_id, channel_id, period),
+                ));
+            }
+            DbMessage::SetTimezone { guild_id, timezone } => {
+                self.settings_mut(guild_id).timezone = timezone;
+            }
+            DbMessage::SetTimeFormat {
+                guild_id,
+                time_format,
+            } => {
+                self.settings_mut(guild_id).time_format = time_format;
+            }
+            DbMessage::SetLanguage { guild_id, language } => {
+                self.settings_mut(guild_id).language = language;
+            }
+            DbMessage::UpdateChannel {
+                guild_id,
+                channel_id,
+                name,
+                parent,
+            } => {
+                let info = self.channel_info.entry(channel_id).or_default();
+                info.guild = guild_id;
+                info.name = name;
+                info.parent = parent;
+                info.deleted = false;
+            }
+            DbMessage::DeleteChannel {
+                guild_id,
+                channel_id,
+                name,
+            } => self.delete_channel(guild_id, channel_id, name),
+            DbMessage::MemberRemoved { guild_id, user_id } => {
+                // Failed lookups report the same member again, they left the first time
+                self.departed_members
+                    .entry((guild_id, user_id))
+                    .or_insert_with(unix_now);
+            }
+            DbMessage::MemberAdded { guild_id, user_id } => {
+                self.departed_members.remove(&(guild_id, user_id));
+            }
+            DbMessage::SetDepartedPolicy { guild_id, policy } => {
+                self.settings_mut(guild_id).departed_policy = policy;
+            }
+            DbMessage::GuildRemoved { guild_id } => {
+                self.guilds.remove(&guild_id);
+                self.removed_guilds.insert(guild_id, unix_now());
+            }
+            DbMessage::GuildAvailable { guild_id } => {
+                self.guilds.insert(guild_id);
+                self.removed_guilds.remove(&guild_id);
+            }
+            DbMessage::SetWebhook { guild_id, webhook } => {
+                self.settings_mut(guild_id).webhook = webhook;
+            }
+            DbMessage::SetArchiveDeletedChannels { guild_id, archive } => {
+                self.settings_mut(guild_id).archive_deleted_channels = archive;
+            }
+            DbMessage::SetRoleExclusion {
+                guild_id,
+                role_id,
+                exclusion,
+            } => {
+                let roles = &mut self.settings_mut(guild_id).excluded_roles;
+                match exclusion {
+                    Some(exclusion) => roles.insert(role_id, exclusion),
+                    None => roles.remove(&role_id),
+                };
+            }
+            DbMessage::SetMaxSession {
+                guild_id,
+                max_session,
+                close,
+            } => {
+                let settings = self.settings_mut(guild_id);
+                settings.max_session = max_session.map(Seconds);
+                settings.close_long_sessions = close;
+            }
+            DbMessage::GetFlaggedSessions {
+                guild_id,
+                limit,
+                http,
+                command,
+            } => {
+                let flagged: Vec<_> = self
+                    .flagged_sessions
+                    .iter()
+                    .rev()
+                    .filter(|flagged| flagged.session.guild == guild_id)
+                    .take(limit)
+                    .map(|flagged| (flagged.session, flagged.credited))
+                    .collect();
+                tokio.spawn(send_flagged_message(
+                    self.settings(guild_id),
+                    http,
+                    command,
+                    flagged,
+                ));
+            }
+            DbMessage::Reply {
+                guild_id,
+                reply,
+                http,
+                command,
+            } => {
+                let settings = guild_id
+                    .map(|g| self.settings(g))
+                    .unwrap_or_else(|| self.default_settings.clone());
+                let text = reply.text(settings.catalog(&command), settings.time_format);
+                tokio.spawn(send_reply(text, http, command));
+            }
+            DbMessage::GetRank {
+                user_id,
+                guild_id,
+                channel_id,
+                period,
+                cache,
+                http,
+                command,
+                db,
+            } => {
+                tokio.spawn(send_rank_message(
+                    guild_id,
+                    user_id,
+                    channel_id.map(|c| self.channel_label(c)),
+                    period,
+                    self.settings(guild_id),
+                    cache,
+                    http,
+                    command,
+                    self.get_rank(user_id, guild_id, channel_id, period),
+                    db,
+                    !self.features.members,
+                ));
+            }
+            DbMessage::AdminAction {
+                guild_id,
+                admin,
+                action,
+                reason,
+            } => {
+                self.apply_admin_action(AuditEntry {
+                    guild: guild_id,
+                    admin,
+                    time: unix_now(),
+                    action,
+                    reason,
+                });
+            }
+            DbMessage::GetAuditLog {
+                guild_id,
+                limit,
+                http,
+                command,
+            } => {
+                let entries: Vec<_> = self
+                    .audit_log
+                    .iter()
+                    .rev()
+                    .filter(|entry| entry.guild == guild_id)
+                    .take(limit)
+                    .map(|entry| (entry.admin, entry.time, entry.action, entry.reason.clone()))
+                    .collect();
+                tokio.spawn(send_audit_message(
+                    self.settings(guild_id),
                     http,
                     command,
-                    self.get_leaderboard(guild_id, channel_id),
+                    entries,
+                ));
+            }
+            DbMessage::Autocomplete {
+                kind,
+                typed,
+                cache,
+                http,
+                autocomplete,
+            } => {
+                let Some(guild_id) = autocomplete.guild_id else {
+                    return;
+                };
+                let suggestions = match kind {
+                    AutocompleteKind::Channel => Suggestions::Channels(
+                        self.get_channel_activity(guild_id)
+                            .into_iter()
+                            .map(|(channel, time)| (channel, self.channel_label(channel), time))
+                            .collect(),
+                    ),
+                    AutocompleteKind::User { channel_id } => {
+                        Suggestions::Users(self.get_leaderboard(guild_id, channel_id, Period::All))
+                    }
+                    AutocompleteKind::Period => Suggestions::Periods(
+                        Period::ALL
+                            .into_iter()
+                            .chain(self.get_active_months(guild_id))
+                            .collect(),
+                    ),
+                };
+                tokio.spawn(send_autocomplete(
+                    suggestions,
+                    self.settings(guild_id),
+                    cache,
+                    http,
+                    autocomplete,
+                    typed,
                 ));
             }
-            DbMessage::StopAndSaveDb { path } => {
+            DbMessage::StopAndSaveDb { path, reply } => {
                 self.shutdown();
-                let mut file = File::create(path).unwrap();
-                self.to_bytes(&mut file).unwrap();
-                println!("Saved DB");
-                std::process::exit(0);
+                let _ = reply.send(self.save(&path));
             }
         }
     }
 }
 
+/// Seconds data of a guild is kept after the bot was removed from it, in case it is added back
+const GUILD_PURGE_DELAY: u64 = 30 * 24 * 60 * 60;
+
+/// How often open sessions are checked against the maximum session length
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Sends messages to the Db and keeps track of how many are waiting
+#[derive(Clone)]
+struct DbSender(Sender<DbMessage>);
+
+impl DbSender {
+    fn send(&self, message: DbMessage) -> Result<(), Box<SendError<DbMessage>>> {
+        METRICS.db_queue_depth.fetch_add(1, Ordering::Relaxed);
+        self.0.send(message).map_err(|err| {
+            METRICS.db_queue_depth.fetch_sub(1, Ordering::Relaxed);
+            Box::new(err)
+        })
+    }
+}
+
 pub struct DbManager {
     _db: Arc<Mutex<Db>>,
-    db_channel: Sender<DbMessage>,
+    db_channel: DbSender,
+    events: broadcast::Sender<VoiceEvent>,
     _db_thread: JoinHandle<()>,
 }
 
 impl DbManager {
-    fn from_db(db: Db) -> Self {
+    fn from_db(mut db: Db, defaults: GuildSettings, features: Features) -> Self {
+        db.default_settings = defaults;
+        db.features = features;
+        let events = db.events.clone();
         let db: Arc<Mutex<Db>> = Arc::new(Mutex::new(db));
         let db_cloned = db.clone();
         let (db_channel, read_channel) = std::sync::mpsc::channel();
         let _db_thread = thread::spawn(move || {
             let mut tokio = tokio::runtime::Runtime::new().unwrap();
             let mut db = db_cloned.lock().unwrap();
-            while let Ok(message) = read_channel.recv() {
-                db.handle_message(message, &mut tokio)
+            let mut last_sweep = Instant::now();
+            loop {
+                match read_channel.recv_timeout(SWEEP_INTERVAL) {
+                    Ok(message) => {
+                        METRICS.db_queue_depth.fetch_sub(1, Ordering::Relaxed);
+                        db.handle_message(message, &mut tokio)
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if last_sweep.elapsed() >= SWEEP_INTERVAL {
+                    db.sweep_sessions();
+                    last_sweep = Instant::now();
+                }
+                let allow_private = db.features.webhooks_allow_private;
+                for (webhook, event, timestamp) in db.webhook_events.drain(..) {
+                    tokio.spawn(webhook::deliver(webhook, event, timestamp, allow_private));
+                }
             }
         });
         Self {
             _db: db,
             _db_thread,
-            db_channel,
+            db_channel: DbSender(db_channel),
+            events,
         }
     }
-    pub fn open(path: PathBuf) -> Result<Self, std::io::Error> {
+    /// Loads the DB from `path`, guilds without settings use `defaults`
+    pub fn open(
+        path: PathBuf,
+        defaults: GuildSettings,
+        features: Features,
+    ) -> Result<Self, std::io::Error> {
         let mut file = File::open(path)?;
-        Ok(Self::from_db(Db::from_bytes(&mut file)?))
+        Ok(Self::from_db(
+            Db::from_bytes(&mut file)?,
+            defaults,
+            features,
+        ))
     }
-    pub fn new() -> Self {
-        Self::from_db(Db::new())
+    pub fn new(defaults: GuildSettings, features: Features) -> Self {
+        Self::from_db(Db::new(), defaults, features)
     }
+    /// Saves the DB in the background, errors are only logged
     pub fn save_db(&self, path: PathBuf) {
-        self.db_channel.send(DbMessage::SaveDb { path }).unwrap();
+        self.db_channel
+            .send(DbMessage::SaveDb { path, reply: None })
+            .unwrap();
+    }
+    /// Saves the DB and waits until it is written
+    pub async fn save_db_checked(&self, path: PathBuf) -> Result<(), std::io::Error> {
+        let (reply, response) = oneshot::channel();
+        self.db_channel
+            .send(DbMessage::SaveDb {
+                path,
+                reply: Some(reply),
+            })
+            .unwrap();
+        response.await.unwrap()
+    }
+    /// Closes all open sessions and saves the DB, the process should exit afterwards
+    pub async fn stop_and_save_db(&self, path: PathBuf) -> Result<(), std::io::Error> {
+        let (reply, response) = oneshot::channel();
+        self.db_channel
+            .send(DbMessage::StopAndSaveDb { path, reply })
+            .unwrap();
+        response.await.unwrap()
+    }
+    pub async fn status(&self) -> DbStatus {
+        let (reply, response) = oneshot::channel();
+        self.db_channel.send(DbMessage::Status { reply }).unwrap();
+        response.await.unwrap()
+    }
+    /// All sessions that are going on, longest first
+    pub async fn open_sessions(&self) -> Vec<OpenSession> {
+        let (reply, response) = oneshot::channel();
+        self.db_channel
+            .send(DbMessage::OpenSessions { reply })
+            .unwrap();
+        response.await.unwrap()
+    }
+    /// Users in voice channels right now, of one guild or all
+    pub async fn live_sessions(&self, guild_id: Option<GuildId>) -> Vec<LiveSession> {
+        let (reply, response) = oneshot::channel();
+        self.db_channel
+            .send(DbMessage::LiveSessions { guild_id, reply })
+            .unwrap();
+        response.await.unwrap()
+    }
+    /// Receives a [VoiceEvent] for every change of a voice channel from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<VoiceEvent> {
+        self.events.subscribe()
+    }
+    /// Total seconds of every user per guild and channel, without opted out users
+    pub async fn export(&self) -> Vec<(UserId, GuildId, ChannelId, u64)> {
+        let (reply, response) = oneshot::channel();
+        self.db_channel.send(DbMessage::Export { reply }).unwrap();
+        response.await.unwrap()
+    }
+    /// Total seconds of a user per guild and channel
+    pub async fn user_totals(&self, user_id: UserId) -> Vec<(GuildId, ChannelId, u64)> {
+        let (reply, response) = oneshot::channel();
+        self.db_channel
+            .send(DbMessage::UserTotals { user_id, reply })
+            .unwrap();
+        response.await.unwrap()
+    }
+    /// Seconds per user, best first, without opted out users
+    ///
+    /// [None] if the bot isn't in the guild
+    pub async fn leaderboard_data(
+        &self,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        period: Period,
+    ) -> Option<Vec<(UserId, u64)>> {
+        let (reply, response) = oneshot::channel();
+        self.db_channel
+            .send(DbMessage::LeaderboardData {
+                guild_id,
+                channel_id,
+                period,
+                reply,
+            })
+            .unwrap();
+        response.await.unwrap()
+    }
+    /// [None] if the bot isn't in the guild or the user opted out or is hidden
+    pub async fn user_stats(
+        &self,
+        user_id: UserId,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        period: Period,
+    ) -> Option<UserStats> {
+        let (reply, response) = oneshot::channel();
+        self.db_channel
+            .send(DbMessage::UserStats {
+                user_id,
+                guild_id,
+                channel_id,
+                period,
+                reply,
+            })
+            .unwrap();
+        response.await.unwrap()
     }
-    pub fn stop_and_save_db(&self, path: PathBuf) {
+    /// Checks the user and channel options are among the suggestions of autocomplete,
+    /// users with any time in the guild are accepted as well
+    pub async fn check_options(
+        &self,
+        guild_id: GuildId,
+        user_id: Option<UserId>,
+        channel_id: Option<ChannelId>,
+    ) -> Result<(), Reply> {
+        let (reply, response) = oneshot::channel();
         self.db_channel
-            .send(DbMessage::StopAndSaveDb { path })
+            .send(DbMessage::CheckOptions {
+                guild_id,
+                user_id,
+                channel_id,
+                reply,
+            })
             .unwrap();
+        response.await.unwrap()
     }
     pub fn get_time(
         &self,
         user_id: UserId,
         guild_id: GuildId,
         channel_id: Option<ChannelId>,
+        period: Period,
         http: Arc<Http>,
         command: ApplicationCommandInteraction,
     ) {
@@ -293,15 +2167,21 @@ impl DbManager {
                 user_id,
                 guild_id,
                 channel_id,
+                period,
                 http,
                 command,
             })
             .unwrap()
     }
+    #[allow(clippy::too_many_arguments)]
     pub fn get_leaderboard(
         &self,
         guild_id: GuildId,
         channel_id: Option<ChannelId>,
+        period: Period,
+        format: LeaderboardFormat,
+        limit: usize,
+        cache: Arc<Cache>,
         http: Arc<Http>,
         command: ApplicationCommandInteraction,
     ) {
@@ -309,37 +2189,280 @@ impl DbManager {
             .send(DbMessage::GetLeaderboard {
                 guild_id,
                 channel_id,
+                period,
+                format,
+                limit,
+                cache,
                 http,
                 command,
+                db: self.db_channel.clone(),
             })
             .unwrap()
     }
-    pub fn add_excluded_user(&self, user_id: UserId) {
-        self.db_channel
-            .send(DbMessage::AddUserToOptOut { user_id })
-            .unwrap();
-    }
-    pub fn remove_excluded_user(&self, user_id: UserId) {
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_rank(
+        &self,
+        user_id: UserId,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        period: Period,
+        cache: Arc<Cache>,
+        http: Arc<Http>,
+        command: ApplicationCommandInteraction,
+    ) {
         self.db_channel
-            .send(DbMessage::RemoverUserToOptOut { user_id })
-            .unwrap();
+            .send(DbMessage::GetRank {
+                user_id,
+                guild_id,
+                channel_id,
+                period,
+                cache,
+                http,
+                command,
+                db: self.db_channel.clone(),
+            })
+            .unwrap()
     }
-    pub fn update_voicestate(
+    pub fn get_heatmap(
         &self,
-        user_id: UserId,
+        guild_id: GuildId,
+        user_id: Option<UserId>,
         channel_id: Option<ChannelId>,
-        guild_id: Option<GuildId>,
+        period: Period,
+        http: Arc<Http>,
+        command: ApplicationCommandInteraction,
     ) {
         self.db_channel
-            .send(DbMessage::UpdateVoicestate {
+            .send(DbMessage::GetHeatmap {
+                guild_id,
                 user_id,
                 channel_id,
+                period,
+                http,
+                command,
+            })
+            .unwrap()
+    }
+    pub fn set_timezone(&self, guild_id: GuildId, timezone: Tz) {
+        self.db_channel
+            .send(DbMessage::SetTimezone { guild_id, timezone })
+            .unwrap();
+    }
+    pub fn set_time_format(&self, guild_id: GuildId, time_format: TimeFormat) {
+        self.db_channel
+            .send(DbMessage::SetTimeFormat {
                 guild_id,
-                time: Instant::now(),
+                time_format,
             })
             .unwrap();
     }
-}
+    /// Suggests values for the option described by `kind`, matching the `typed` text
+    pub fn autocomplete(
+        &self,
+        kind: AutocompleteKind,
+        typed: String,
+        cache: Arc<Cache>,
+        http: Arc<Http>,
+        autocomplete: AutocompleteInteraction,
+    ) {
+        self.db_channel
+            .send(DbMessage::Autocomplete {
+                kind,
+                typed,
+                cache,
+                http,
+                autocomplete,
+            })
+            .unwrap();
+    }
+    pub fn set_language(&self, guild_id: GuildId, language: Option<Language>) {
+        self.db_channel
+            .send(DbMessage::SetLanguage { guild_id, language })
+            .unwrap();
+    }
+    /// Remembers the name and category of a channel, so its time counts towards the category
+    pub fn update_channel(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        name: String,
+        parent: Option<ChannelId>,
+    ) {
+        self.db_channel
+            .send(DbMessage::UpdateChannel {
+                guild_id,
+                channel_id,
+                name,
+                parent,
+            })
+            .unwrap();
+    }
+    pub fn delete_channel(&self, guild_id: GuildId, channel_id: ChannelId, name: String) {
+        self.db_channel
+            .send(DbMessage::DeleteChannel {
+                guild_id,
+                channel_id,
+                name,
+            })
+            .unwrap();
+    }
+    /// The member left the guild, what happens to their data depends on the [DepartedPolicy]
+    pub fn member_removed(&self, guild_id: GuildId, user_id: UserId) {
+        self.db_channel
+            .send(DbMessage::MemberRemoved { guild_id, user_id })
+            .unwrap();
+    }
+    /// The member joined the guild, data of an earlier membership is shown again
+    pub fn member_added(&self, guild_id: GuildId, user_id: UserId) {
+        self.db_channel
+            .send(DbMessage::MemberAdded { guild_id, user_id })
+            .unwrap();
+    }
+    pub fn set_departed_policy(&self, guild_id: GuildId, policy: DepartedPolicy) {
+        self.db_channel
+            .send(DbMessage::SetDepartedPolicy { guild_id, policy })
+            .unwrap();
+    }
+    /// The bot was removed from the guild, its data is purged if it isn't added back in time
+    pub fn guild_removed(&self, guild_id: GuildId) {
+        self.db_channel
+            .send(DbMessage::GuildRemoved { guild_id })
+            .unwrap();
+    }
+    pub fn guild_available(&self, guild_id: GuildId) {
+        self.db_channel
+            .send(DbMessage::GuildAvailable { guild_id })
+            .unwrap();
+    }
+    /// Sends events of the guild to the webhook, [None] stops sending them
+    pub fn set_webhook(&self, guild_id: GuildId, webhook: Option<Webhook>) {
+        self.db_channel
+            .send(DbMessage::SetWebhook { guild_id, webhook })
+            .unwrap();
+    }
+    pub fn set_archive_deleted_channels(&self, guild_id: GuildId, archive: bool) {
+        self.db_channel
+            .send(DbMessage::SetArchiveDeletedChannels { guild_id, archive })
+            .unwrap();
+    }
+    /// Excludes members with the role from tracking or leaderboards, [None] includes them again
+    pub fn set_role_exclusion(
+        &self,
+        guild_id: GuildId,
+        role_id: RoleId,
+        exclusion: Option<RoleExclusion>,
+    ) {
+        self.db_channel
+            .send(DbMessage::SetRoleExclusion {
+                guild_id,
+                role_id,
+                exclusion,
+            })
+            .unwrap();
+    }
+    /// Limits how long a single session counts, [None] removes the limit
+    /// With `close` sessions are ended as soon as they reach the limit
+    pub fn set_max_session(&self, guild_id: GuildId, max_session: Option<u64>, close: bool) {
+        self.db_channel
+            .send(DbMessage::SetMaxSession {
+                guild_id,
+                max_session,
+                close,
+            })
+            .unwrap();
+    }
+    pub fn get_flagged_sessions(
+        &self,
+        guild_id: GuildId,
+        limit: usize,
+        http: Arc<Http>,
+        command: ApplicationCommandInteraction,
+    ) {
+        self.db_channel
+            .send(DbMessage::GetFlaggedSessions {
+                guild_id,
+                limit,
+                http,
+                command,
+            })
+            .unwrap();
+    }
+    /// Applies an [AdminAction] and records it in the audit log of the guild
+    pub fn admin_action(
+        &self,
+        guild_id: GuildId,
+        admin: UserId,
+        action: AdminAction,
+        reason: String,
+    ) {
+        self.db_channel
+            .send(DbMessage::AdminAction {
+                guild_id,
+                admin,
+                action,
+                reason,
+            })
+            .unwrap();
+    }
+    pub fn get_audit_log(
+        &self,
+        guild_id: GuildId,
+        limit: usize,
+        http: Arc<Http>,
+        command: ApplicationCommandInteraction,
+    ) {
+        self.db_channel
+            .send(DbMessage::GetAuditLog {
+                guild_id,
+                limit,
+                http,
+                command,
+            })
+            .unwrap();
+    }
+    /// Answers `command` with a short [Reply] in the language of the guild
+    /// The reply is sent after all previously queued messages are handled
+    pub fn reply(&self, reply: Reply, http: Arc<Http>, command: ApplicationCommandInteraction) {
+        self.db_channel
+            .send(DbMessage::Reply {
+                guild_id: command.guild_id,
+                reply,
+                http,
+                command,
+            })
+            .unwrap();
+    }
+    pub fn add_excluded_user(&self, user_id: UserId) {
+        self.db_channel
+            .send(DbMessage::AddUserToOptOut { user_id })
+            .unwrap();
+    }
+    pub fn remove_excluded_user(&self, user_id: UserId) {
+        self.db_channel
+            .send(DbMessage::RemoverUserToOptOut { user_id })
+            .unwrap();
+    }
+    /// Starts or ends the session of a member, `roles` are checked against the excluded roles
+    pub fn update_voicestate(
+        &self,
+        user_id: UserId,
+        channel_id: Option<ChannelId>,
+        guild_id: Option<GuildId>,
+        roles: Vec<RoleId>,
+        audience: bool,
+    ) {
+        self.db_channel
+            .send(DbMessage::UpdateVoicestate {
+                user_id,
+                channel_id,
+                guild_id,
+                roles,
+                audience,
+                time: Instant::now(),
+            })
+            .unwrap();
+    }
+}
 
 enum DbMessage {
     AddUserToOptOut {
@@ -352,24 +2475,180 @@ enum DbMessage {
         user_id: UserId,
         channel_id: Option<ChannelId>,
         guild_id: Option<GuildId>,
+        roles: Vec<RoleId>,
+        audience: bool,
         time: Instant,
     },
     SaveDb {
         path: PathBuf,
+        reply: Option<oneshot::Sender<Result<(), std::io::Error>>>,
     },
     StopAndSaveDb {
         path: PathBuf,
+        reply: oneshot::Sender<Result<(), std::io::Error>>,
+    },
+    Status {
+        reply: oneshot::Sender<DbStatus>,
+    },
+    OpenSessions {
+        reply: oneshot::Sender<Vec<OpenSession>>,
+    },
+    Export {
+        reply: oneshot::Sender<Vec<(UserId, GuildId, ChannelId, u64)>>,
+    },
+    LiveSessions {
+        guild_id: Option<GuildId>,
+        reply: oneshot::Sender<Vec<LiveSession>>,
+    },
+    UserTotals {
+        user_id: UserId,
+        reply: oneshot::Sender<Vec<(GuildId, ChannelId, u64)>>,
+    },
+    LeaderboardData {
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        period: Period,
+        reply: oneshot::Sender<Option<Vec<(UserId, u64)>>>,
+    },
+    UserStats {
+        user_id: UserId,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        period: Period,
+        reply: oneshot::Sender<Option<UserStats>>,
+    },
+    CheckOptions {
+        guild_id: GuildId,
+        user_id: Option<UserId>,
+        channel_id: Option<ChannelId>,
+        reply: oneshot::Sender<Result<(), Reply>>,
     },
     GetTime {
         user_id: UserId,
         guild_id: GuildId,
         channel_id: Option<ChannelId>,
+        period: Period,
         http: Arc<Http>,
         command: ApplicationCommandInteraction,
     },
     GetLeaderboard {
         guild_id: GuildId,
         channel_id: Option<ChannelId>,
+        period: Period,
+        format: LeaderboardFormat,
+        limit: usize,
+        cache: Arc<Cache>,
+        http: Arc<Http>,
+        command: ApplicationCommandInteraction,
+        /// Members whose name lookup shows they left are reported back
+        db: DbSender,
+    },
+    GetRank {
+        user_id: UserId,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        period: Period,
+        cache: Arc<Cache>,
+        http: Arc<Http>,
+        command: ApplicationCommandInteraction,
+        /// Members that are looked up and turn out to have left are reported back
+        db: DbSender,
+    },
+    GetHeatmap {
+        guild_id: GuildId,
+        user_id: Option<UserId>,
+        channel_id: Option<ChannelId>,
+        period: Period,
+        http: Arc<Http>,
+        command: ApplicationCommandInteraction,
+    },
+    SetTimezone {
+        guild_id: GuildId,
+        timezone: Tz,
+    },
+    SetTimeFormat {
+        guild_id: GuildId,
+        time_format: TimeFormat,
+    },
+    SetLanguage {
+        guild_id: GuildId,
+        language: Option<Language>,
+    },
+    UpdateChannel {
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        name: String,
+        parent: Option<ChannelId>,
+    },
+    DeleteChannel {
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        name: String,
+    },
+    MemberRemoved {
+        guild_id: GuildId,
+        user_id: UserId,
+    },
+    MemberAdded {
+        guild_id: GuildId,
+        user_id: UserId,
+    },
+    SetDepartedPolicy {
+        guild_id: GuildId,
+        policy: DepartedPolicy,
+    },
+    GuildRemoved {
+        guild_id: GuildId,
+    },
+    GuildAvailable {
+        guild_id: GuildId,
+    },
+    SetWebhook {
+        guild_id: GuildId,
+        webhook: Option<Webhook>,
+    },
+    SetArchiveDeletedChannels {
+        guild_id: GuildId,
+        archive: bool,
+    },
+    SetRoleExclusion {
+        guild_id: GuildId,
+        role_id: RoleId,
+        exclusion: Option<RoleExclusion>,
+    },
+    SetMaxSession {
+        guild_id: GuildId,
+        max_session: Option<u64>,
+        close: bool,
+    },
+    GetFlaggedSessions {
+        guild_id: GuildId,
+        limit: usize,
+        http: Arc<Http>,
+        command: ApplicationCommandInteraction,
+    },
+    AdminAction {
+        guild_id: GuildId,
+        admin: UserId,
+        action: AdminAction,
+        reason: String,
+    },
+    GetAuditLog {
+        guild_id: GuildId,
+        limit: usize,
+        http: Arc<Http>,
+        command: ApplicationCommandInteraction,
+    },
+    Autocomplete {
+        kind: AutocompleteKind,
+        typed: String,
+        cache: Arc<Cache>,
+        http: Arc<Http>,
+        autocomplete: AutocompleteInteraction,
+    },
+    Reply {
+        guild_id: Option<GuildId>,
+        reply: Reply,
         http: Arc<Http>,
         command: ApplicationCommandInteraction,
     },
@@ -381,61 +2660,895 @@ fn read_u64(reader: &mut dyn Read) -> Result<u64, std::io::Error> {
     Ok(u64::from_le_bytes(buffer))
 }
 
+fn read_string(reader: &mut dyn Read) -> Result<String, std::io::Error> {
+    let mut buffer = vec![0u8; read_u64(reader)? as usize];
+    reader.read_exact(&mut buffer)?;
+    String::from_utf8(buffer).map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))
+}
+
+fn write_string(writer: &mut dyn Write, value: &str) -> Result<(), std::io::Error> {
+    writer.write_all(&(value.len() as u64).to_le_bytes())?;
+    writer.write_all(value.as_bytes())
+}
+
+/// Turns an [UnexpectedEof][ErrorKind::UnexpectedEof] into [None]
+/// Used for data that was appended to the file format later on
+fn optional<T>(result: Result<T, std::io::Error>) -> Result<Option<T>, std::io::Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn send_time_message(
     user_id: UserId,
     _guild_id: GuildId,
-    channel_id: Option<ChannelId>,
+    channel: Option<ChannelLabel>,
+    period: Period,
+    settings: GuildSettings,
     http: Arc<Http>,
     command: ApplicationCommandInteraction,
     time: Seconds,
+    audience: Seconds,
 ) {
-    let time = humantime::format_duration(Duration::from_secs(time.0)).to_string();
-    let mut msg = MessageBuilder::new();
-    msg.push(format!("<@{}>", user_id.0))
-        .push(" war ")
-        .push(time);
-    let text = if let Some(channel) = channel_id {
-        msg.push(" in ").channel(channel).build()
+    let catalog = settings.catalog(&command);
+    let user = format!("<@{}>", user_id.0);
+    let speaker = settings
+        .time_format
+        .format(time.0 - audience.0.min(time.0), catalog);
+    let time = settings.time_format.format(time.0, catalog);
+    let mut text = if let Some(channel) = channel {
+        let channel = channel.text(catalog);
+        fill(
+            catalog.voice_time,
+            &[("user", &user), ("time", &time), ("channel", &channel)],
+        )
     } else {
-        msg.push(" in einem VC").build()
+        fill(catalog.voice_time_any, &[("user", &user), ("time", &time)])
     };
-    command
+    if period != Period::All {
+        text.push_str(&format!(" ({})", period.label(catalog)));
+    }
+    if audience.0 > 0 {
+        text.push('\n');
+        text.push_str(&fill(
+            catalog.stage_time,
+            &[
+                ("speaker", &speaker),
+                (
+                    "audience",
+                    &settings.time_format.format(audience.0, catalog),
+                ),
+            ],
+        ));
+    }
+    let result = command
         .create_interaction_response(&http, |interaction| {
             interaction.interaction_response_data(|data| data.content(text).flags(SILENT_FLAG))
         })
-        .await
-        .unwrap();
+        .await;
+    record_response(&command, result);
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn send_leaderboard_message(
-    channel_id: Option<ChannelId>,
+    guild_id: GuildId,
+    channel: Option<ChannelLabel>,
+    period: Period,
+    format: LeaderboardFormat,
+    settings: GuildSettings,
+    cache: Arc<Cache>,
     http: Arc<Http>,
     command: ApplicationCommandInteraction,
     leaderboard: Vec<(UserId, Seconds)>,
+    db: DbSender,
+    check_members: bool,
 ) {
+    let catalog = settings.catalog(&command);
+    let mut title = if let Some(channel) = channel {
+        fill(
+            catalog.leaderboard_channel,
+            &[("channel", &channel.text(catalog))],
+        )
+    } else {
+        catalog.leaderboard.to_string()
+    };
+    if period != Period::All {
+        title.push_str(&format!(" ({})", period.label(catalog)));
+    }
     let mut embed = CreateEmbed::default();
-    embed.title(format!(
-        "VC Leaderboard {}",
-        if let Some(channel_id) = channel_id {
-            format!("for <#{}>", channel_id)
-        } else {
-            String::new()
+    embed.title(&title);
+    if format == LeaderboardFormat::Text {
+        let mut msg = MessageBuilder::new();
+        for (user, time) in leaderboard.iter() {
+            let time = settings.time_format.format(time.0, catalog);
+            msg.mention(user).push(": ").push(time).push("\n");
+        }
+        embed.description(msg.build());
+        let result = command
+            .create_interaction_response(&http, |interaction| {
+                interaction
+                    .interaction_response_data(|data| data.add_embed(embed).flags(SILENT_FLAG))
+            })
+            .await;
+        record_response(&command, result);
+        if check_members {
+            let users = leaderboard.iter().map(|(user, _)| *user).collect();
+            report_departed(&cache, &http, &db, guild_id, users).await;
+        }
+        return;
+    }
+    // Looking up the names can take longer than the few seconds Discord waits for a response
+    let deferred = command
+        .create_interaction_response(&http, |interaction| {
+            interaction
+                .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|data| data.flags(SILENT_FLAG))
+        })
+        .await;
+    if deferred.is_err() {
+        return record_response(&command, deferred);
+    }
+    let mut entries = Vec::with_capacity(leaderboard.len());
+    for (rank, (user, time)) in leaderboard.iter().enumerate() {
+        let name = display_name(&cache, &http, &db, guild_id, *user).await;
+        let label = settings.time_format.format(time.0, catalog);
+        entries.push((format!("{}. {}", rank + 1, name), time.0, label));
+    }
+    let chart_title = match period {
+        Period::All => catalog.leaderboard.to_string(),
+        period => format!("{} ({})", catalog.leaderboard, period.label(catalog)),
+    };
+    let image = render::bar_chart(&chart_title, &entries);
+    embed.image("attachment://leaderboard.png");
+    let result = command
+        .create_followup_message(&http, |followup| {
+            followup
+                .add_file(AttachmentType::Bytes {
+                    data: Cow::Owned(image),
+                    filename: "leaderboard.png".to_string(),
+                })
+                .add_embed(embed)
+        })
+        .await;
+    record_response(&command, result.map(|_| ()));
+}
+
+/// Sends the rank, with `check_members` the members shown are looked up afterwards
+#[allow(clippy::too_many_arguments)]
+async fn send_rank_message(
+    guild_id: GuildId,
+    user_id: UserId,
+    channel: Option<ChannelLabel>,
+    period: Period,
+    settings: GuildSettings,
+    cache: Arc<Cache>,
+    http: Arc<Http>,
+    command: ApplicationCommandInteraction,
+    rank: Option<Rank>,
+    db: DbSender,
+    check_members: bool,
+) {
+    let catalog = settings.catalog(&command);
+    let user = format!("<@{}>", user_id.0);
+    let mut title = catalog.rank.to_string();
+    if let Some(channel) = channel {
+        title.push_str(&format!(" {}", channel.text(catalog)));
+    }
+    if period != Period::All {
+        title.push_str(&format!(" ({})", period.label(catalog)));
+    }
+    let mut embed = CreateEmbed::default();
+    embed.title(title);
+    let shown: Vec<UserId> = rank
+        .iter()
+        .flat_map(|rank| rank.neighbors.iter().map(|(_, user, _)| *user))
+        .collect();
+    match rank {
+        Some(rank) => {
+            let mut msg = MessageBuilder::new();
+            msg.push_line(fill(
+                catalog.rank_position,
+                &[
+                    ("user", &user),
+                    ("position", &rank.position),
+                    ("total", &rank.total),
+                    ("time", &settings.time_format.format(rank.time.0, catalog)),
+                ],
+            ));
+            if let Some((above, gap)) = rank.gap {
+                msg.push_line(fill(
+                    catalog.rank_gap,
+                    &[
+                        ("gap", &settings.time_format.format(gap.0, catalog)),
+                        ("user", &format!("<@{}>", above.0)),
+                    ],
+                ));
+            }
+            msg.push_line("");
+            for (position, neighbor, time) in rank.neighbors {
+                let line = format!(
+                    "#{position} <@{}>: {}",
+                    neighbor.0,
+                    settings.time_format.format(time.0, catalog)
+                );
+                if neighbor == user_id {
+                    msg.push_bold_line(line);
+                } else {
+                    msg.push_line(line);
+                }
+            }
+            embed.description(msg.build());
+        }
+        None => {
+            embed.description(fill(catalog.rank_none, &[("user", &user)]));
+        }
+    }
+    let result = command
+        .create_interaction_response(&http, |interaction| {
+            interaction.interaction_response_data(|data| data.add_embed(embed).flags(SILENT_FLAG))
+        })
+        .await;
+    record_response(&command, result);
+    if check_members {
+        report_departed(&cache, &http, &db, guild_id, shown).await;
+    }
+}
+
+/// Resolves the name a member is shown with, the user id if the lookup fails
+async fn display_name(
+    cache: &Arc<Cache>,
+    http: &Http,
+    db: &DbSender,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> String {
+    match lookup_member(cache, http, db, guild_id, user_id).await {
+        Some(member) => member.display_name().into_owned(),
+        None => user_id.to_string(),
+    }
+}
+
+/// Looks the members up, so the ones that left are hidden from then on
+///
+/// Responses are sent before, members that left still show up in that one
+async fn report_departed(
+    cache: &Arc<Cache>,
+    http: &Http,
+    db: &DbSender,
+    guild_id: GuildId,
+    users: Vec<UserId>,
+) {
+    for user_id in users {
+        lookup_member(cache, http, db, guild_id, user_id).await;
+    }
+}
+
+/// Looks up a member, using the cache before asking the API
+///
+/// Without the guild members intent the bot isn't told about members leaving,
+/// a lookup answered with `Unknown Member` is reported as the member having left instead
+async fn lookup_member(
+    cache: &Arc<Cache>,
+    http: &Http,
+    db: &DbSender,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Option<Member> {
+    match guild_id.member((cache, http), user_id).await {
+        Ok(member) => Some(member),
+        Err(serenity::Error::Http(err)) if is_unknown_member(&err) => {
+            let _ = db.send(DbMessage::MemberRemoved { guild_id, user_id });
+            None
         }
-    ));
+        Err(_) => None,
+    }
+}
+
+fn is_unknown_member(err: &HttpError) -> bool {
+    matches!(err, HttpError::UnsuccessfulRequest(response) if response.error.code == UNKNOWN_MEMBER)
+}
+
+async fn send_heatmap_message(
+    period: Period,
+    settings: GuildSettings,
+    http: Arc<Http>,
+    command: ApplicationCommandInteraction,
+    heatmap: [[u64; 24]; 7],
+) {
+    let catalog = settings.catalog(&command);
+    let title = match period {
+        Period::All => format!("{} ({})", catalog.heatmap, settings.timezone.name()),
+        period => format!(
+            "{} ({}, {})",
+            catalog.heatmap,
+            period.label(catalog),
+            settings.timezone.name()
+        ),
+    };
+    let image = render::heatmap(&title, &catalog.weekdays, &heatmap);
+    let result = command
+        .create_interaction_response(&http, |interaction| {
+            interaction.interaction_response_data(|data| {
+                data.add_file(AttachmentType::Bytes {
+                    data: Cow::Owned(image),
+                    filename: "heatmap.png".to_string(),
+                })
+                .flags(SILENT_FLAG)
+            })
+        })
+        .await;
+    record_response(&command, result);
+}
+
+/// Sends the latest entries of the audit log, given as admin, time, action and reason
+async fn send_audit_message(
+    settings: GuildSettings,
+    http: Arc<Http>,
+    command: ApplicationCommandInteraction,
+    entries: Vec<(UserId, u64, AdminAction, String)>,
+) {
+    let catalog = settings.catalog(&command);
     let mut msg = MessageBuilder::new();
-    for (user, time) in leaderboard.iter().take(10) {
-        let time = humantime::format_duration(Duration::from_secs(time.0)).to_string();
-        msg.mention(user).push(": ").push(time).push("\n");
+    if entries.is_empty() {
+        msg.push(catalog.audit_empty);
     }
-    embed.description(msg.build());
-    command
+    for (admin, time, action, reason) in entries {
+        let admin = format!("<@{}>", admin.0);
+        let text = match action {
+            AdminAction::AdjustTime {
+                user,
+                channel,
+                seconds,
+            } => fill(
+                catalog.audit_adjust_time,
+                &[
+                    ("admin", &admin),
+                    ("user", &format!("<@{}>", user.0)),
+                    ("channel", &format!("<#{}>", channel.0)),
+                    (
+                        "time",
+                        &settings.time_format.format_signed(seconds, catalog),
+                    ),
+                ],
+            ),
+            AdminAction::ResetUser { user } => fill(
+                catalog.audit_reset_user,
+                &[("admin", &admin), ("user", &format!("<@{}>", user.0))],
+            ),
+            AdminAction::ResetChannel { channel } => fill(
+                catalog.audit_reset_channel,
+                &[("admin", &admin), ("channel", &format!("<#{}>", channel.0))],
+            ),
+        };
+        msg.push_line(format!("<t:{time}:f> {text}"));
+        if !reason.is_empty() {
+            msg.push("> ").push_line_safe(reason);
+        }
+    }
+    let mut embed = CreateEmbed::default();
+    embed.title(catalog.audit_log).description(msg.build());
+    let result = command
         .create_interaction_response(&http, |interaction| {
-            interaction.interaction_response_data(
-                |data: &mut serenity::builder::CreateInteractionResponseData<'_>| {
-                    data.add_embed(embed).flags(SILENT_FLAG)
-                },
-            )
+            interaction.interaction_response_data(|data| data.add_embed(embed).ephemeral(true))
+        })
+        .await;
+    record_response(&command, result);
+}
+
+/// Sends the latest flagged sessions with the time that was credited for them
+async fn send_flagged_message(
+    settings: GuildSettings,
+    http: Arc<Http>,
+    command: ApplicationCommandInteraction,
+    flagged: Vec<(Session, Seconds)>,
+) {
+    let catalog = settings.catalog(&command);
+    let mut msg = MessageBuilder::new();
+    if flagged.is_empty() {
+        msg.push(catalog.flagged_empty);
+    }
+    for (session, credited) in flagged {
+        let text = fill(
+            catalog.flagged_session,
+            &[
+                ("user", &format!("<@{}>", session.user.0)),
+                ("channel", &format!("<#{}>", session.channel.0)),
+                (
+                    "time",
+                    &settings.time_format.format(session.duration.0, catalog),
+                ),
+                (
+                    "excess",
+                    &settings
+                        .time_format
+                        .format(session.duration.0 - credited.0, catalog),
+                ),
+            ],
+        );
+        msg.push_line(format!("<t:{}:f> {text}", session.start));
+    }
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(catalog.flagged_sessions)
+        .description(msg.build());
+    let result = command
+        .create_interaction_response(&http, |interaction| {
+            interaction.interaction_response_data(|data| data.add_embed(embed).ephemeral(true))
+        })
+        .await;
+    record_response(&command, result);
+}
+
+/// Counts the response to a command, failing to send it is only logged
+fn record_response(command: &ApplicationCommandInteraction, result: serenity::Result<()>) {
+    let name = &command.data.name;
+    match result {
+        Ok(()) => METRICS.interaction(name, "ok"),
+        Err(err) => {
+            METRICS.interaction(name, "error");
+            error!("Responding to /{name} failed: {err}");
+        }
+    }
+}
+
+async fn send_reply(text: String, http: Arc<Http>, command: ApplicationCommandInteraction) {
+    let result = command
+        .create_interaction_response(&http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| data.ephemeral(true).content(text))
         })
-        .await
-        .unwrap();
+        .await;
+    record_response(&command, result);
+}
+
+/// JSON error code of Discord for a user that isn't a member of the guild
+const UNKNOWN_MEMBER: isize = 10007;
+
+/// Number of suggestions Discord accepts at most
+const MAX_SUGGESTIONS: usize = 25;
+
+async fn send_autocomplete(
+    suggestions: Suggestions,
+    settings: GuildSettings,
+    cache: Arc<Cache>,
+    http: Arc<Http>,
+    autocomplete: AutocompleteInteraction,
+    typed: String,
+) {
+    let catalog = settings.catalog_for(&autocomplete.locale, autocomplete.guild_locale.as_deref());
+    let typed = typed.to_lowercase();
+    let choices: Vec<(String, String)> = match suggestions {
+        Suggestions::Channels(channels) => channels
+            .into_iter()
+            .map(|(channel, label, time)| {
+                let name = label.name(&cache, catalog);
+                let time = settings.time_format.format(time.0, catalog);
+                (format!("{name} ({time})"), channel.to_string())
+            })
+            .collect(),
+        Suggestions::Users(users) => users
+            .into_iter()
+            .map(|(user, time)| {
+                let name = autocomplete
+                    .guild_id
+                    .and_then(|guild| cache.member(guild, user))
+                    .map(|member| member.display_name().into_owned())
+                    .or_else(|| cache.user(user).map(|user| user.name))
+                    .unwrap_or_else(|| user.to_string());
+                let time = settings.time_format.format(time.0, catalog);
+                (format!("{name} ({time})"), user.to_string())
+            })
+            .collect(),
+        Suggestions::Periods(periods) => periods
+            .into_iter()
+            .map(|period| (period.label(catalog), period.name()))
+            .collect(),
+    };
+    let result = autocomplete
+        .create_autocomplete_response(&http, |response| {
+            choices
+                .into_iter()
+                .filter(|(name, value)| {
+                    name.to_lowercase().contains(&typed) || value.starts_with(&typed)
+                })
+                .take(MAX_SUGGESTIONS)
+                .for_each(|(name, value)| {
+                    response.add_string_choice(name, value);
+                });
+            response
+        })
+        .await;
+    // Autocompletions are outdated as soon as the user types on, failing to send one is fine
+    if let Err(err) = result {
+        warn!("{err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId(10);
+    const CHANNEL: ChannelId = ChannelId(20);
+    const STAGE: ChannelId = ChannelId(21);
+    const CATEGORY: ChannelId = ChannelId(22);
+    const USER: UserId = UserId(30);
+
+    /// A session in [CHANNEL], which is in [CATEGORY], or in the audience of [STAGE]
+    fn session(start: u64, audience: bool) -> Session {
+        Session {
+            user: USER,
+            guild: GUILD,
+            channel: if audience { STAGE } else { CHANNEL },
+            start,
+            duration: Seconds(600),
+            audience,
+            parent: Some(CATEGORY).filter(|_| !audience),
+        }
+    }
+
+    type SessionFields = (
+        UserId,
+        GuildId,
+        ChannelId,
+        u64,
+        Seconds,
+        bool,
+        Option<ChannelId>,
+    );
+
+    fn session_fields(session: &Session) -> SessionFields {
+        (
+            session.user,
+            session.guild,
+            session.channel,
+            session.start,
+            session.duration,
+            session.audience,
+            session.parent,
+        )
+    }
+
+    fn reload(db: &Db) -> Db {
+        let mut bytes = Vec::new();
+        db.to_bytes(&mut bytes).unwrap();
+        Db::from_bytes(&mut Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut db = Db::new();
+        db.excluded_users.insert(UserId(31));
+        db.voice_times.insert(
+            USER,
+            HashMap::from([
+                ((GUILD, CHANNEL), Seconds(1200)),
+                ((GUILD, STAGE), Seconds(600)),
+            ]),
+        );
+        db.audience_times
+            .insert(USER, HashMap::from([((GUILD, STAGE), Seconds(600))]));
+        db.sessions = vec![session(1000, false), session(2000, true)];
+        let mut settings = GuildSettings::defaults(
+            chrono_tz::Europe::Berlin,
+            TimeFormat::Hours,
+            Some(Language::German),
+            Some(3600),
+            true,
+        );
+        settings.close_long_sessions = true;
+        settings
+            .excluded_roles
+            .insert(RoleId(40), RoleExclusion::Leaderboard);
+        settings.departed_policy = DepartedPolicy::Delete { days: 7 };
+        settings.webhook = Some(Webhook {
+            url: "https://example.com/hook".to_string(),
+            secret: "secret".to_string(),
+        });
+        db.guild_settings.insert(GUILD, settings);
+        db.audit_log.push(AuditEntry {
+            guild: GUILD,
+            admin: UserId(32),
+            time: 3000,
+            action: AdminAction::AdjustTime {
+                user: USER,
+                channel: CHANNEL,
+                seconds: -300,
+            },
+            reason: "double counted".to_string(),
+        });
+        db.flagged_sessions.push(FlaggedSession {
+            session: session(4000, false),
+            credited: Seconds(300),
+        });
+        db.flagged_sessions.push(FlaggedSession {
+            session: session(5000, true),
+            credited: Seconds(400),
+        });
+        db.hidden_members.insert((GUILD, UserId(33)));
+        db.channel_info.insert(
+            CHANNEL,
+            ChannelInfo {
+                parent: Some(CATEGORY),
+                guild: GUILD,
+                name: "General".to_string(),
+                deleted: true,
+            },
+        );
+        db.removed_guilds.insert(GuildId(11), 5000);
+        db.departed_members.insert((GUILD, UserId(34)), 6000);
+
+        let loaded = reload(&db);
+        assert_eq!(loaded.excluded_users, db.excluded_users);
+        assert_eq!(loaded.voice_times, db.voice_times);
+        assert_eq!(loaded.audience_times, db.audience_times);
+        let mut sessions: Vec<_> = loaded.sessions.iter().map(session_fields).collect();
+        sessions.sort();
+        let expected: Vec<_> = db.sessions.iter().map(session_fields).collect();
+        assert_eq!(sessions, expected);
+        let settings = &loaded.guild_settings[&GUILD];
+        assert_eq!(settings.timezone, chrono_tz::Europe::Berlin);
+        assert_eq!(settings.time_format, TimeFormat::Hours);
+        assert_eq!(settings.language, Some(Language::German));
+        assert_eq!(settings.max_session, Some(Seconds(3600)));
+        assert!(settings.close_long_sessions);
+        assert_eq!(
+            settings.excluded_roles,
+            HashMap::from([(RoleId(40), RoleExclusion::Leaderboard)])
+        );
+        assert!(settings.archive_deleted_channels);
+        assert_eq!(settings.departed_policy, DepartedPolicy::Delete { days: 7 });
+        let webhook = settings.webhook.as_ref().unwrap();
+        assert_eq!(webhook.url, "https://example.com/hook");
+        assert_eq!(webhook.secret, "secret");
+        let [entry] = loaded.audit_log.as_slice() else {
+            panic!("expected one audit entry");
+        };
+        assert_eq!(
+            (entry.guild, entry.admin, entry.time),
+            (GUILD, UserId(32), 3000)
+        );
+        assert_eq!(entry.reason, "double counted");
+        assert!(matches!(
+            entry.action,
+            AdminAction::AdjustTime {
+                user: USER,
+                channel: CHANNEL,
+                seconds: -300
+            }
+        ));
+        let [flagged, flagged_audience] = loaded.flagged_sessions.as_slice() else {
+            panic!("expected two flagged sessions");
+        };
+        assert_eq!(
+            session_fields(&flagged.session),
+            session_fields(&session(4000, false))
+        );
+        assert_eq!(flagged.credited, Seconds(300));
+        assert_eq!(
+            session_fields(&flagged_audience.session),
+            session_fields(&session(5000, true))
+        );
+        assert_eq!(flagged_audience.credited, Seconds(400));
+        assert_eq!(loaded.hidden_members, db.hidden_members);
+        let info = &loaded.channel_info[&CHANNEL];
+        assert_eq!(info.parent, Some(CATEGORY));
+        assert_eq!(info.guild, GUILD);
+        assert_eq!(info.name, "General");
+        assert!(info.deleted);
+        assert_eq!(loaded.removed_guilds, db.removed_guilds);
+        assert_eq!(loaded.departed_members, db.departed_members);
+    }
+
+    #[test]
+    fn load_without_optional_sections() {
+        // A file as written before sessions were recorded
+        let mut bytes = Vec::new();
+        for value in [1, 31, 1, USER.0, 1, GUILD.0, CHANNEL.0, 1200] {
+            bytes.extend_from_slice(&u64::to_le_bytes(value));
+        }
+        let db = Db::from_bytes(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(db.excluded_users, HashSet::from([UserId(31)]));
+        assert_eq!(
+            db.voice_times,
+            HashMap::from([(USER, HashMap::from([((GUILD, CHANNEL), Seconds(1200))]))])
+        );
+        assert!(db.sessions.is_empty());
+        assert!(db.guild_settings.is_empty());
+        assert!(db.audit_log.is_empty());
+        assert!(db.flagged_sessions.is_empty());
+        assert!(db.hidden_members.is_empty());
+        assert!(db.audience_times.is_empty());
+        assert!(db.channel_info.is_empty());
+        assert!(db.removed_guilds.is_empty());
+        assert!(db.departed_members.is_empty());
+    }
+
+    #[test]
+    fn load_settings_without_optional_fields() {
+        // Settings as written before languages could be configured
+        let mut settings = Vec::new();
+        write_string(&mut settings, "Europe/Berlin").unwrap();
+        settings.extend_from_slice(&TimeFormat::Short.to_u64().to_le_bytes());
+        // No excluded users, voice times or sessions, then one guild with the settings
+        let mut bytes = Vec::new();
+        for value in [0, 0, 0, 1, GUILD.0, settings.len() as u64] {
+            bytes.extend_from_slice(&u64::to_le_bytes(value));
+        }
+        bytes.extend_from_slice(&settings);
+        let db = Db::from_bytes(&mut Cursor::new(bytes)).unwrap();
+        let settings = &db.guild_settings[&GUILD];
+        assert_eq!(settings.timezone, chrono_tz::Europe::Berlin);
+        assert_eq!(settings.time_format, TimeFormat::Short);
+        assert_eq!(settings.language, None);
+        assert_eq!(settings.max_session, None);
+        assert_eq!(settings.departed_policy, DepartedPolicy::Keep);
+        assert!(settings.webhook.is_none());
+    }
+
+    #[test]
+    fn load_truncated_section() {
+        // A section that was started has to be complete, only missing sections are optional
+        let mut bytes = Vec::new();
+        for value in [0, 0, 1, USER.0] {
+            bytes.extend_from_slice(&u64::to_le_bytes(value));
+        }
+        let err = Db::from_bytes(&mut Cursor::new(bytes)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn moved_channel_keeps_session_category() {
+        let mut db = Db::new();
+        db.sessions.push(session(1000, false));
+        // The channel was moved to another category after the session
+        db.channel_info.insert(
+            CHANNEL,
+            ChannelInfo {
+                parent: Some(ChannelId(23)),
+                guild: GUILD,
+                ..ChannelInfo::default()
+            },
+        );
+        let range = (0, u64::MAX);
+        let time = |filter| {
+            db.get_period_times(GUILD, Some(filter), range)
+                .get(&USER)
+                .copied()
+        };
+        assert_eq!(time(CATEGORY), Some(600));
+        assert_eq!(time(CHANNEL), Some(600));
+        assert_eq!(time(ChannelId(23)), None);
+    }
+
+    #[test]
+    fn load_sessions_without_categories() {
+        let mut db = Db::new();
+        db.sessions.push(Session {
+            parent: None,
+            ..session(1000, false)
+        });
+        db.channel_info.insert(
+            CHANNEL,
+            ChannelInfo {
+                parent: Some(CATEGORY),
+                guild: GUILD,
+                ..ChannelInfo::default()
+            },
+        );
+        let mut bytes = Vec::new();
+        db.to_bytes(&mut bytes).unwrap();
+        // Drop the categories of the one session, as files written before had them
+        bytes.truncate(bytes.len() - 2 * 8);
+        let db = Db::from_bytes(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(db.sessions[0].parent, Some(CATEGORY));
+    }
+
+    /// A guild with seven users, user 1 has 7000 seconds down to user 7 with 1000
+    fn ranked_db() -> Db {
+        let mut db = Db::new();
+        for n in 1..=7 {
+            let time = Seconds((8 - n) * 1000);
+            db.voice_times
+                .insert(UserId(n), HashMap::from([((GUILD, CHANNEL), time)]));
+        }
+        db
+    }
+
+    fn rank(db: &Db, user: u64) -> Option<Rank> {
+        db.get_rank(UserId(user), GUILD, None, Period::All)
+    }
+
+    fn neighbor_positions(rank: &Rank) -> Vec<(usize, UserId)> {
+        rank.neighbors
+            .iter()
+            .map(|(p, user, _)| (*p, *user))
+            .collect()
+    }
+
+    #[test]
+    fn rank_first_place() {
+        let rank = rank(&ranked_db(), 1).unwrap();
+        assert_eq!(
+            (rank.position, rank.total, rank.time),
+            (1, 7, Seconds(7000))
+        );
+        assert!(rank.gap.is_none());
+        assert_eq!(
+            neighbor_positions(&rank),
+            [(1, UserId(1)), (2, UserId(2)), (3, UserId(3))]
+        );
+    }
+
+    #[test]
+    fn rank_second_place() {
+        let rank = rank(&ranked_db(), 2).unwrap();
+        assert_eq!((rank.position, rank.time), (2, Seconds(6000)));
+        assert_eq!(rank.gap, Some((UserId(1), Seconds(1000))));
+        assert_eq!(
+            neighbor_positions(&rank),
+            [
+                (1, UserId(1)),
+                (2, UserId(2)),
+                (3, UserId(3)),
+                (4, UserId(4))
+            ]
+        );
+    }
+
+    #[test]
+    fn rank_middle() {
+        let mut db = ranked_db();
+        // Shorter gap to the user above
+        db.voice_times
+            .get_mut(&UserId(4))
+            .unwrap()
+            .insert((GUILD, STAGE), Seconds(700));
+        let rank = rank(&db, 4).unwrap();
+        assert_eq!((rank.position, rank.time), (4, Seconds(4700)));
+        assert_eq!(rank.gap, Some((UserId(3), Seconds(300))));
+        assert_eq!(
+            neighbor_positions(&rank),
+            [
+                (2, UserId(2)),
+                (3, UserId(3)),
+                (4, UserId(4)),
+                (5, UserId(5)),
+                (6, UserId(6))
+            ]
+        );
+    }
+
+    #[test]
+    fn rank_last_place() {
+        let rank = rank(&ranked_db(), 7).unwrap();
+        assert_eq!(
+            (rank.position, rank.total, rank.time),
+            (7, 7, Seconds(1000))
+        );
+        assert_eq!(rank.gap, Some((UserId(6), Seconds(1000))));
+        assert_eq!(
+            neighbor_positions(&rank),
+            [(5, UserId(5)), (6, UserId(6)), (7, UserId(7))]
+        );
+    }
+
+    #[test]
+    fn rank_without_time() {
+        assert!(rank(&ranked_db(), 8).is_none());
+        assert!(ranked_db()
+            .get_rank(UserId(1), GuildId(11), None, Period::All)
+            .is_none());
+    }
 }
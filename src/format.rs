@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use crate::i18n::{fill, Catalog};
+
+/// How durations are written in responses, configured per guild
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// `3h 12m 5s`
+    #[default]
+    Full,
+    /// `3h 12m`
+    Short,
+    /// `3.2 hours`
+    Hours,
+    /// `192 minutes`
+    Minutes,
+}
+
+impl TimeFormat {
+    pub const ALL: [TimeFormat; 4] = [Self::Full, Self::Short, Self::Hours, Self::Minutes];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Short => "short",
+            Self::Hours => "hours",
+            Self::Minutes => "minutes",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+    pub fn to_u64(self) -> u64 {
+        self as u64
+    }
+    pub fn from_u64(value: u64) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+    /// Formats a number of seconds with the units of the given [Catalog]
+    pub fn format(self, seconds: u64, catalog: &Catalog) -> String {
+        match self {
+            Self::Full => humantime::format_duration(Duration::from_secs(seconds)).to_string(),
+            Self::Short => {
+                let (hours, minutes) = (seconds / 3600, seconds % 3600 / 60);
+                if hours > 0 {
+                    format!("{hours}h {minutes}m")
+                } else {
+                    format!("{minutes}m")
+                }
+            }
+            Self::Hours => {
+                let hours = format!("{:.1}", seconds as f64 / 3600.0)
+                    .replace('.', &catalog.decimal_separator.to_string());
+                fill(catalog.hours, &[("value", &hours)])
+            }
+            Self::Minutes => fill(catalog.minutes, &[("value", &(seconds / 60))]),
+        }
+    }
+    /// Like [format][Self::format], but with a leading `+` or `-`
+    pub fn format_signed(self, seconds: i64, catalog: &Catalog) -> String {
+        let sign = if seconds < 0 { '-' } else { '+' };
+        format!("{sign}{}", self.format(seconds.unsigned_abs(), catalog))
+    }
+    /// Parses a duration like `+1h 30m` or `-45m` into seconds, without sign it is positive
+    pub fn parse_signed(value: &str) -> Option<i64> {
+        let value = value.trim();
+        let (negative, value) = match value.strip_prefix('-') {
+            Some(value) => (true, value),
+            None => (false, value.strip_prefix('+').unwrap_or(value)),
+        };
+        let seconds =
+            i64::try_from(humantime::parse_duration(value.trim()).ok()?.as_secs()).ok()?;
+        Some(if negative { -seconds } else { seconds })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_signed() {
+        assert_eq!(TimeFormat::parse_signed("+1h 30m"), Some(5400));
+        assert_eq!(TimeFormat::parse_signed("1h 30m"), Some(5400));
+        assert_eq!(TimeFormat::parse_signed("-45m"), Some(-2700));
+        assert_eq!(TimeFormat::parse_signed(" - 2h "), Some(-7200));
+        assert_eq!(TimeFormat::parse_signed("+0s"), Some(0));
+    }
+
+    #[test]
+    fn parse_signed_invalid() {
+        assert_eq!(TimeFormat::parse_signed(""), None);
+        assert_eq!(TimeFormat::parse_signed("-"), None);
+        assert_eq!(TimeFormat::parse_signed("+-1h"), None);
+        assert_eq!(TimeFormat::parse_signed("90"), None);
+        assert_eq!(TimeFormat::parse_signed("an hour"), None);
+        // Doesn't fit into an i64
+        assert_eq!(TimeFormat::parse_signed("300000000000y"), None);
+    }
+}
@@ -0,0 +1,785 @@
+use std::fmt::Display;
+
+use chrono_tz::Tz;
+use serenity::model::prelude::{ChannelId, RoleId, UserId};
+
+use crate::db::DepartedPolicy;
+use crate::format::TimeFormat;
+
+/// Language responses and command descriptions are written in
+///
+/// Adding a language means adding a variant and a [Catalog] for it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Self::English, Self::German];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::German => "de",
+        }
+    }
+    /// Parses a language code, region suffixes like in `en-US` are ignored
+    pub fn from_code(code: &str) -> Option<Self> {
+        let code = code.split('-').next().unwrap_or_default();
+        Self::ALL
+            .into_iter()
+            .find(|language| language.code() == code)
+    }
+    /// Name of the language in the language itself
+    pub fn native_name(self) -> &'static str {
+        match self {
+            Self::English => "English",
+            Self::German => "Deutsch",
+        }
+    }
+    /// Locales Discord uses for this language
+    pub fn discord_locales(self) -> &'static [&'static str] {
+        match self {
+            Self::English => &["en-US", "en-GB"],
+            Self::German => &["de"],
+        }
+    }
+    pub fn catalog(self) -> &'static Catalog {
+        match self {
+            Self::English => &ENGLISH,
+            Self::German => &GERMAN,
+        }
+    }
+    pub fn to_u64(self) -> u64 {
+        self as u64
+    }
+    pub fn from_u64(value: u64) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+    /// Picks the language of a response
+    ///
+    /// A language configured for the guild wins over the locale of the user,
+    /// which wins over the locale of the guild
+    pub fn select(configured: Option<Language>, locale: &str, guild_locale: Option<&str>) -> Self {
+        configured
+            .or_else(|| Self::from_code(locale))
+            .or_else(|| guild_locale.and_then(Self::from_code))
+            .unwrap_or_default()
+    }
+}
+
+/// All texts of one [Language]
+///
+/// Templates contain placeholders like `{user}` that are replaced with [fill]
+pub struct Catalog {
+    /// Localized names of commands and options, keyed by their English name
+    pub names: &'static [(&'static str, &'static str)],
+    /// Descriptions of commands and options, keyed by their path like `get_vc_time.user`
+    pub descriptions: &'static [(&'static str, &'static str)],
+    pub opted_out: &'static str,
+    pub opted_in: &'static str,
+    pub voice_time: &'static str,
+    pub voice_time_any: &'static str,
+    pub leaderboard: &'static str,
+    pub leaderboard_channel: &'static str,
+    pub heatmap: &'static str,
+    pub weekdays: [&'static str; 7],
+    /// Labels in the order of [Period::ALL][crate::period::Period::ALL]
+    pub periods: [&'static str; 5],
+    pub months: [&'static str; 12],
+    pub hours: &'static str,
+    pub minutes: &'static str,
+    pub decimal_separator: char,
+    pub timezone_set: &'static str,
+    pub unknown_timezone: &'static str,
+    pub time_format_set: &'static str,
+    pub unknown_time_format: &'static str,
+    pub language_set: &'static str,
+    pub language_reset: &'static str,
+    pub automatic: &'static str,
+    pub unknown_user: &'static str,
+    pub unknown_channel: &'static str,
+    pub unknown_period: &'static str,
+    pub rank: &'static str,
+    pub rank_position: &'static str,
+    pub rank_gap: &'static str,
+    pub rank_none: &'static str,
+    pub audit_log: &'static str,
+    pub audit_empty: &'static str,
+    pub audit_adjust_time: &'static str,
+    pub audit_reset_user: &'static str,
+    pub audit_reset_channel: &'static str,
+    pub time_adjusted: &'static str,
+    pub user_reset: &'static str,
+    pub channel_reset: &'static str,
+    pub invalid_duration: &'static str,
+    pub max_session_set: &'static str,
+    pub max_session_close: &'static str,
+    pub max_session_disabled: &'static str,
+    pub flagged_sessions: &'static str,
+    pub flagged_empty: &'static str,
+    pub flagged_session: &'static str,
+    pub role_excluded_tracking: &'static str,
+    pub role_excluded_leaderboard: &'static str,
+    pub role_included: &'static str,
+    pub stage_time: &'static str,
+    pub deleted_channel: &'static str,
+    pub archived_channels: &'static str,
+    pub archive_enabled: &'static str,
+    pub archive_disabled: &'static str,
+    pub departed_keep: &'static str,
+    pub departed_hide: &'static str,
+    pub departed_delete: &'static str,
+    /// Choices of `/settings departed`: keep, hide and delete
+    pub departed_policies: [&'static str; 3],
+    /// Choices of `/settings exclude_role`: tracking, leaderboard and off
+    pub exclusion_modes: [&'static str; 3],
+    pub webhook_set: &'static str,
+    pub webhook_disabled: &'static str,
+    pub invalid_webhook: &'static str,
+}
+
+impl Catalog {
+    /// Localized name of a command or option, [None] if it keeps its English name
+    pub fn name(&self, key: &str) -> Option<&'static str> {
+        lookup(self.names, key)
+    }
+    /// Description of a command or option, falling back to English
+    pub fn description(&self, key: &str) -> &'static str {
+        lookup(self.descriptions, key)
+            .or_else(|| lookup(ENGLISH.descriptions, key))
+            .unwrap_or("-")
+    }
+}
+
+fn lookup(entries: &'static [(&'static str, &'static str)], key: &str) -> Option<&'static str> {
+    entries
+        .iter()
+        .find(|(entry, _)| *entry == key)
+        .map(|(_, value)| *value)
+}
+
+/// Replaces the `{name}` placeholders of a template
+pub fn fill(template: &str, values: &[(&str, &dyn Display)]) -> String {
+    let mut text = template.to_string();
+    for (name, value) in values {
+        text = text.replace(&format!("{{{name}}}"), &value.to_string());
+    }
+    text
+}
+
+/// Short confirmation or error replies that don't need data from the [Db][crate::db::Db]
+pub enum Reply {
+    OptedOut,
+    OptedIn,
+    TimezoneSet(Tz),
+    UnknownTimezone,
+    TimeFormatSet(TimeFormat),
+    UnknownTimeFormat,
+    LanguageSet(Language),
+    LanguageReset,
+    UnknownUser,
+    UnknownChannel,
+    UnknownPeriod,
+    TimeAdjusted {
+        user: UserId,
+        channel: ChannelId,
+        seconds: i64,
+    },
+    UserReset(UserId),
+    ChannelReset(ChannelId),
+    InvalidDuration,
+    MaxSessionSet {
+        seconds: u64,
+        close: bool,
+    },
+    MaxSessionDisabled,
+    /// The role is now left out of tracking, or just leaderboards if `tracking` is false
+    RoleExcluded {
+        role: RoleId,
+        tracking: bool,
+    },
+    RoleIncluded(RoleId),
+    ArchiveDeletedChannels(bool),
+    DepartedPolicySet(DepartedPolicy),
+    WebhookSet(String),
+    WebhookDisabled,
+    InvalidWebhook,
+}
+
+impl Reply {
+    /// The reply in the language of `catalog`, durations are formatted with `time_format`
+    pub fn text(&self, catalog: &Catalog, time_format: TimeFormat) -> String {
+        match self {
+            Self::OptedOut => catalog.opted_out.to_string(),
+            Self::OptedIn => catalog.opted_in.to_string(),
+            Self::TimezoneSet(timezone) => {
+                fill(catalog.timezone_set, &[("timezone", &timezone.name())])
+            }
+            Self::UnknownTimezone => catalog.unknown_timezone.to_string(),
+            Self::TimeFormatSet(time_format) => fill(
+                catalog.time_format_set,
+                &[("example", &time_format.format(11525, catalog))],
+            ),
+            Self::UnknownTimeFormat => catalog.unknown_time_format.to_string(),
+            Self::LanguageSet(language) => fill(
+                catalog.language_set,
+                &[("language", &language.native_name())],
+            ),
+            Self::LanguageReset => catalog.language_reset.to_string(),
+            Self::UnknownUser => catalog.unknown_user.to_string(),
+            Self::UnknownChannel => catalog.unknown_channel.to_string(),
+            Self::UnknownPeriod => catalog.unknown_period.to_string(),
+            Self::TimeAdjusted {
+                user,
+                channel,
+                seconds,
+            } => fill(
+                catalog.time_adjusted,
+                &[
+                    ("user", &format!("<@{}>", user.0)),
+                    ("channel", &format!("<#{}>", channel.0)),
+                    ("time", &time_format.format_signed(*seconds, catalog)),
+                ],
+            ),
+            Self::UserReset(user) => {
+                fill(catalog.user_reset, &[("user", &format!("<@{}>", user.0))])
+            }
+            Self::ChannelReset(channel) => fill(
+                catalog.channel_reset,
+                &[("channel", &format!("<#{}>", channel.0))],
+            ),
+            Self::InvalidDuration => catalog.invalid_duration.to_string(),
+            Self::MaxSessionSet { seconds, close } => {
+                let template = if *close {
+                    catalog.max_session_close
+                } else {
+                    catalog.max_session_set
+                };
+                fill(
+                    template,
+                    &[("time", &time_format.format(*seconds, catalog))],
+                )
+            }
+            Self::MaxSessionDisabled => catalog.max_session_disabled.to_string(),
+            Self::RoleExcluded { role, tracking } => {
+                let template = if *tracking {
+                    catalog.role_excluded_tracking
+                } else {
+                    catalog.role_excluded_leaderboard
+                };
+                fill(template, &[("role", &format!("<@&{}>", role.0))])
+            }
+            Self::ArchiveDeletedChannels(true) => catalog.archive_enabled.to_string(),
+            Self::ArchiveDeletedChannels(false) => catalog.archive_disabled.to_string(),
+            Self::DepartedPolicySet(DepartedPolicy::Keep) => catalog.departed_keep.to_string(),
+            Self::DepartedPolicySet(DepartedPolicy::Hide) => catalog.departed_hide.to_string(),
+            Self::DepartedPolicySet(DepartedPolicy::Delete { days }) => {
+                fill(catalog.departed_delete, &[("days", days)])
+            }
+            Self::RoleIncluded(role) => fill(
+                catalog.role_included,
+                &[("role", &format!("<@&{}>", role.0))],
+            ),
+            Self::WebhookSet(url) => fill(catalog.webhook_set, &[("url", url)]),
+            Self::WebhookDisabled => catalog.webhook_disabled.to_string(),
+            Self::InvalidWebhook => catalog.invalid_webhook.to_string(),
+        }
+    }
+}
+
+static ENGLISH: Catalog = Catalog {
+    names: &[],
+    descriptions: &[
+        ("opt_out", "Opt out of voice chat data aggregation"),
+        ("opt_in", "Opt into voice chat data aggregation"),
+        ("get_vc_time", "Get VC time of a user"),
+        ("get_vc_time.user", "User that should be queried"),
+        (
+            "get_vc_time.channel",
+            "Channel or category that should be queried",
+        ),
+        ("get_vc_time.period", "Time range that should be queried"),
+        ("leaderboard", "Load the Leaderboard"),
+        (
+            "leaderboard.channel",
+            "Channel or category, all-time totals count for the category a channel is in now",
+        ),
+        (
+            "leaderboard.period",
+            "Time range that the leaderboard should be for",
+        ),
+        ("leaderboard.format", "How the leaderboard should be shown"),
+        ("leaderboard.limit", "Number of users that should be shown"),
+        ("rank", "Show the position of a user on the leaderboard"),
+        ("rank.user", "User that should be queried, defaults to you"),
+        (
+            "rank.channel",
+            "Channel or category, all-time totals count for the category a channel is in now",
+        ),
+        (
+            "rank.period",
+            "Time range that the leaderboard should be for",
+        ),
+        ("heatmap", "Show voice activity per weekday and hour"),
+        ("heatmap.user", "User that should be queried"),
+        (
+            "heatmap.channel",
+            "Channel or category that should be queried",
+        ),
+        ("heatmap.period", "Time range that should be queried"),
+        ("settings", "Configure the bot for this server"),
+        ("settings.timezone", "Set the timezone used for statistics"),
+        (
+            "settings.timezone.name",
+            "Name of the timezone, e.g. Europe/Berlin",
+        ),
+        ("settings.time_format", "Set how durations are written"),
+        ("settings.time_format.style", "Format for durations"),
+        (
+            "settings.language",
+            "Set the language of the bot's responses",
+        ),
+        (
+            "settings.language.language",
+            "Language for all responses on this server",
+        ),
+        (
+            "settings.max_session",
+            "Limit how long a single session counts",
+        ),
+        (
+            "settings.max_session.duration",
+            "Maximum length, e.g. 6h, or off",
+        ),
+        (
+            "settings.max_session.close",
+            "End sessions as soon as they reach the limit",
+        ),
+        (
+            "settings.exclude_role",
+            "Leave members with a role out of statistics",
+        ),
+        ("settings.exclude_role.role", "Role that should be excluded"),
+        (
+            "settings.exclude_role.mode",
+            "What the role should be excluded from",
+        ),
+        (
+            "settings.archive_deleted",
+            "Choose what happens to the time of deleted channels",
+        ),
+        (
+            "settings.archive_deleted.enabled",
+            "Merge the time of deleted channels into one archive",
+        ),
+        (
+            "settings.webhook",
+            "Send voice events to a webhook, without a url no events are sent",
+        ),
+        ("settings.webhook.url", "URL the events are posted to"),
+        (
+            "settings.webhook.secret",
+            "Secret the events are signed with",
+        ),
+        (
+            "settings.departed",
+            "Choose what happens to members that left the server",
+        ),
+        (
+            "settings.departed.policy",
+            "What should happen to their data",
+        ),
+        (
+            "settings.departed.days",
+            "Days until data is deleted, 30 if not given",
+        ),
+        ("admin", "Correct recorded voice times"),
+        ("admin.adjust_time", "Add or remove voice time of a user"),
+        (
+            "admin.adjust_time.user",
+            "User whose time should be adjusted",
+        ),
+        (
+            "admin.adjust_time.channel",
+            "Channel the time should be adjusted in",
+        ),
+        (
+            "admin.adjust_time.duration",
+            "Time to add, e.g. +1h 30m or -45m",
+        ),
+        ("admin.adjust_time.reason", "Why the time is adjusted"),
+        ("admin.reset_user", "Delete all recorded time of a user"),
+        ("admin.reset_user.user", "User whose time should be deleted"),
+        ("admin.reset_user.reason", "Why the time is deleted"),
+        (
+            "admin.reset_channel",
+            "Delete all recorded time in a channel",
+        ),
+        (
+            "admin.reset_channel.channel",
+            "Channel whose time should be deleted",
+        ),
+        ("admin.reset_channel.reason", "Why the time is deleted"),
+        (
+            "admin.flagged",
+            "Show sessions that were longer than the maximum",
+        ),
+        (
+            "admin.flagged.limit",
+            "Number of sessions that should be shown",
+        ),
+        ("admin.audit", "Show the latest admin actions"),
+        (
+            "admin.audit.limit",
+            "Number of actions that should be shown",
+        ),
+    ],
+    opted_out: "You are now opting out of voice channel data aggregation.",
+    opted_in: "You are now opting into voice channel data aggregation.",
+    voice_time: "{user} was in {channel} for {time}",
+    voice_time_any: "{user} was in a VC for {time}",
+    leaderboard: "VC Leaderboard",
+    leaderboard_channel: "VC Leaderboard for {channel}",
+    heatmap: "Voice activity",
+    weekdays: ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+    periods: ["all time", "today", "this week", "this month", "this year"],
+    months: [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ],
+    hours: "{value} hours",
+    minutes: "{value} minutes",
+    decimal_separator: '.',
+    timezone_set: "The timezone of this server is now {timezone}.",
+    unknown_timezone: "Unknown timezone, please use a name like `Europe/Berlin`.",
+    time_format_set: "Durations are now written like {example}.",
+    unknown_time_format: "Unknown time format.",
+    language_set: "Responses on this server are now in {language}.",
+    language_reset: "Responses now use the language of each user.",
+    automatic: "automatic",
+    unknown_user: "Unknown user, please pick one of the suggestions or use a mention.",
+    unknown_channel: "Unknown channel, please pick one of the suggestions.",
+    unknown_period: "Unknown period, please pick one of the suggestions.",
+    rank: "Rank",
+    rank_position: "{user} is **#{position}** of {total} with {time}",
+    rank_gap: "{gap} behind {user}",
+    rank_none: "{user} has no recorded voice time yet.",
+    audit_log: "Audit log",
+    audit_empty: "No admin actions were recorded yet.",
+    audit_adjust_time: "{admin} adjusted {user} in {channel} by {time}",
+    audit_reset_user: "{admin} reset {user}",
+    audit_reset_channel: "{admin} reset {channel}",
+    time_adjusted: "Adjusted the voice time of {user} in {channel} by {time}.",
+    user_reset: "Deleted all recorded voice time of {user}.",
+    channel_reset: "Deleted all recorded voice time in {channel}.",
+    invalid_duration: "Invalid duration, use something like +1h 30m or -45m.",
+    max_session_set: "Sessions now count for at most {time}, longer ones are flagged.",
+    max_session_close: "Sessions are now closed and flagged after {time}.",
+    max_session_disabled: "Sessions are no longer limited.",
+    flagged_sessions: "Flagged sessions",
+    flagged_empty: "No sessions were flagged yet.",
+    flagged_session: "{user} in {channel} for {time}, {excess} were not counted",
+    role_excluded_tracking: "Voice time of members with {role} is no longer recorded.",
+    role_excluded_leaderboard: "Members with {role} no longer show up on leaderboards.",
+    role_included: "Members with {role} are counted again.",
+    exclusion_modes: ["tracking", "leaderboard", "off"],
+    stage_time: "Speaking: {speaker}, in the audience: {audience}",
+    deleted_channel: "#{name} (deleted)",
+    archived_channels: "Archived channels",
+    archive_enabled: "Time of deleted channels is now merged into archived channels.",
+    archive_disabled: "Deleted channels now keep their time under their last name.",
+    departed_keep: "Members that left now stay on leaderboards.",
+    departed_hide: "Members that left are now hidden from leaderboards until they rejoin.",
+    departed_delete:
+        "Members that left are now hidden and their data is deleted after {days} days.",
+    departed_policies: ["keep", "hide", "delete"],
+    webhook_set: "Voice events are now sent to {url}.",
+    webhook_disabled: "Voice events are no longer sent to a webhook.",
+    invalid_webhook: "The webhook needs an https url of a public host and a secret.",
+};
+
+static GERMAN: Catalog = Catalog {
+    names: &[
+        ("opt_out", "nicht_erfassen"),
+        ("opt_in", "erfassen"),
+        ("get_vc_time", "vc_zeit"),
+        ("leaderboard", "rangliste"),
+        ("rank", "rang"),
+        ("heatmap", "aktivitaet"),
+        ("settings", "einstellungen"),
+        ("user", "nutzer"),
+        ("channel", "kanal"),
+        ("period", "zeitraum"),
+        ("limit", "anzahl"),
+        ("timezone", "zeitzone"),
+        ("time_format", "zeitformat"),
+        ("style", "stil"),
+        ("language", "sprache"),
+        ("Voice time", "Sprachzeit"),
+        ("adjust_time", "zeit_anpassen"),
+        ("reset_user", "nutzer_zuruecksetzen"),
+        ("reset_channel", "kanal_zuruecksetzen"),
+        ("audit", "protokoll"),
+        ("duration", "dauer"),
+        ("reason", "grund"),
+        ("max_session", "max_sitzung"),
+        ("close", "beenden"),
+        ("flagged", "markiert"),
+        ("exclude_role", "rolle_ausnehmen"),
+        ("role", "rolle"),
+        ("mode", "modus"),
+        ("archive_deleted", "geloeschte_archivieren"),
+        ("enabled", "aktiv"),
+        ("departed", "ausgetreten"),
+        ("policy", "umgang"),
+        ("days", "tage"),
+        ("secret", "geheimnis"),
+    ],
+    descriptions: &[
+        ("opt_out", "Sprachkanal-Zeiten nicht mehr erfassen"),
+        ("opt_in", "Sprachkanal-Zeiten wieder erfassen"),
+        ("get_vc_time", "VC-Zeit eines Nutzers abfragen"),
+        ("get_vc_time.user", "Nutzer, der abgefragt werden soll"),
+        (
+            "get_vc_time.channel",
+            "Kanal oder Kategorie, die abgefragt werden soll",
+        ),
+        ("get_vc_time.period", "Zeitraum, der abgefragt werden soll"),
+        ("leaderboard", "Rangliste anzeigen"),
+        (
+            "leaderboard.channel",
+            "Kanal oder Kategorie, Gesamtzeiten zählen für die jetzige Kategorie eines Kanals",
+        ),
+        (
+            "leaderboard.period",
+            "Zeitraum, für den die Rangliste gelten soll",
+        ),
+        (
+            "leaderboard.format",
+            "Wie die Rangliste angezeigt werden soll",
+        ),
+        ("leaderboard.limit", "Anzahl der angezeigten Nutzer"),
+        ("rank", "Position eines Nutzers in der Rangliste anzeigen"),
+        (
+            "rank.user",
+            "Nutzer, der abgefragt werden soll, standardmäßig du",
+        ),
+        (
+            "rank.channel",
+            "Kanal oder Kategorie, Gesamtzeiten zählen für die jetzige Kategorie eines Kanals",
+        ),
+        ("rank.period", "Zeitraum, für den die Rangliste gelten soll"),
+        (
+            "heatmap",
+            "Sprachaktivität nach Wochentag und Uhrzeit anzeigen",
+        ),
+        ("heatmap.user", "Nutzer, der abgefragt werden soll"),
+        (
+            "heatmap.channel",
+            "Kanal oder Kategorie, die abgefragt werden soll",
+        ),
+        ("heatmap.period", "Zeitraum, der abgefragt werden soll"),
+        ("settings", "Den Bot für diesen Server einstellen"),
+        ("settings.timezone", "Zeitzone für Statistiken festlegen"),
+        (
+            "settings.timezone.name",
+            "Name der Zeitzone, z.B. Europe/Berlin",
+        ),
+        (
+            "settings.time_format",
+            "Festlegen, wie Zeiten geschrieben werden",
+        ),
+        ("settings.time_format.style", "Format für Zeiten"),
+        ("settings.language", "Sprache der Antworten festlegen"),
+        (
+            "settings.language.language",
+            "Sprache aller Antworten auf diesem Server",
+        ),
+        (
+            "settings.max_session",
+            "Begrenzen, wie lange eine Sitzung zählt",
+        ),
+        (
+            "settings.max_session.duration",
+            "Maximale Länge, z.B. 6h, oder off",
+        ),
+        (
+            "settings.max_session.close",
+            "Sitzungen beenden, sobald sie die Grenze erreichen",
+        ),
+        (
+            "settings.exclude_role",
+            "Mitglieder mit einer Rolle aus Statistiken ausnehmen",
+        ),
+        (
+            "settings.exclude_role.role",
+            "Rolle, die ausgenommen werden soll",
+        ),
+        (
+            "settings.exclude_role.mode",
+            "Wovon die Rolle ausgenommen werden soll",
+        ),
+        (
+            "settings.archive_deleted",
+            "Festlegen, was mit der Zeit gelöschter Kanäle passiert",
+        ),
+        (
+            "settings.archive_deleted.enabled",
+            "Die Zeit gelöschter Kanäle in einem Archiv zusammenfassen",
+        ),
+        (
+            "settings.webhook",
+            "Sprachereignisse an einen Webhook senden, ohne URL werden keine gesendet",
+        ),
+        ("settings.webhook.url", "URL, an die Ereignisse gesendet werden"),
+        (
+            "settings.webhook.secret",
+            "Geheimnis, mit dem Ereignisse signiert werden",
+        ),
+        (
+            "settings.departed",
+            "Festlegen, was mit Mitgliedern passiert, die den Server verlassen",
+        ),
+        ("settings.departed.policy", "Was mit ihren Daten passieren soll"),
+        ("settings.departed.days", "Tage bis zur Löschung, standardmäßig 30"),
+        ("admin", "Erfasste Sprachzeiten korrigieren"),
+        (
+            "admin.adjust_time",
+            "Sprachzeit eines Nutzers hinzufügen oder abziehen",
+        ),
+        (
+            "admin.adjust_time.user",
+            "Nutzer, dessen Zeit angepasst werden soll",
+        ),
+        (
+            "admin.adjust_time.channel",
+            "Kanal, in dem die Zeit angepasst werden soll",
+        ),
+        (
+            "admin.adjust_time.duration",
+            "Hinzuzufügende Zeit, z.B. +1h 30m oder -45m",
+        ),
+        ("admin.adjust_time.reason", "Warum die Zeit angepasst wird"),
+        (
+            "admin.reset_user",
+            "Alle erfassten Zeiten eines Nutzers löschen",
+        ),
+        (
+            "admin.reset_user.user",
+            "Nutzer, dessen Zeiten gelöscht werden sollen",
+        ),
+        (
+            "admin.reset_user.reason",
+            "Warum die Zeiten gelöscht werden",
+        ),
+        (
+            "admin.reset_channel",
+            "Alle erfassten Zeiten in einem Kanal löschen",
+        ),
+        (
+            "admin.reset_channel.channel",
+            "Kanal, dessen Zeiten gelöscht werden sollen",
+        ),
+        (
+            "admin.reset_channel.reason",
+            "Warum die Zeiten gelöscht werden",
+        ),
+        ("admin.audit", "Die letzten Admin-Aktionen anzeigen"),
+        ("admin.audit.limit", "Anzahl der angezeigten Aktionen"),
+        (
+            "admin.flagged",
+            "Sitzungen anzeigen, die länger als erlaubt waren",
+        ),
+        ("admin.flagged.limit", "Anzahl der angezeigten Sitzungen"),
+    ],
+    opted_out: "Deine Sprachkanal-Zeiten werden nicht mehr erfasst.",
+    opted_in: "Deine Sprachkanal-Zeiten werden jetzt erfasst.",
+    voice_time: "{user} war {time} in {channel}",
+    voice_time_any: "{user} war {time} in einem VC",
+    leaderboard: "VC-Rangliste",
+    leaderboard_channel: "VC-Rangliste für {channel}",
+    heatmap: "Sprachaktivität",
+    weekdays: ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"],
+    periods: [
+        "insgesamt",
+        "heute",
+        "diese Woche",
+        "diesen Monat",
+        "dieses Jahr",
+    ],
+    months: [
+        "Januar",
+        "Februar",
+        "März",
+        "April",
+        "Mai",
+        "Juni",
+        "Juli",
+        "August",
+        "September",
+        "Oktober",
+        "November",
+        "Dezember",
+    ],
+    hours: "{value} Stunden",
+    minutes: "{value} Minuten",
+    decimal_separator: ',',
+    timezone_set: "Die Zeitzone dieses Servers ist jetzt {timezone}.",
+    unknown_timezone: "Unbekannte Zeitzone, bitte einen Namen wie `Europe/Berlin` verwenden.",
+    time_format_set: "Zeiten werden jetzt wie {example} geschrieben.",
+    unknown_time_format: "Unbekanntes Zeitformat.",
+    language_set: "Antworten auf diesem Server sind jetzt auf {language}.",
+    language_reset: "Antworten verwenden jetzt die Sprache des jeweiligen Nutzers.",
+    automatic: "automatisch",
+    unknown_user: "Unbekannter Nutzer, bitte einen Vorschlag oder eine Erwähnung verwenden.",
+    unknown_channel: "Unbekannter Kanal, bitte einen der Vorschläge verwenden.",
+    unknown_period: "Unbekannter Zeitraum, bitte einen der Vorschläge verwenden.",
+    rank: "Rang",
+    rank_position: "{user} ist **#{position}** von {total} mit {time}",
+    rank_gap: "{gap} hinter {user}",
+    rank_none: "{user} hat noch keine erfasste Sprachzeit.",
+    audit_log: "Protokoll",
+    audit_empty: "Es wurden noch keine Admin-Aktionen erfasst.",
+    audit_adjust_time: "{admin} hat {user} in {channel} um {time} angepasst",
+    audit_reset_user: "{admin} hat {user} zurückgesetzt",
+    audit_reset_channel: "{admin} hat {channel} zurückgesetzt",
+    time_adjusted: "Die Sprachzeit von {user} in {channel} wurde um {time} angepasst.",
+    user_reset: "Alle erfassten Sprachzeiten von {user} wurden gelöscht.",
+    channel_reset: "Alle erfassten Sprachzeiten in {channel} wurden gelöscht.",
+    invalid_duration: "Ungültige Dauer, verwende etwas wie +1h 30m oder -45m.",
+    max_session_set: "Sitzungen zählen jetzt höchstens {time}, längere werden markiert.",
+    max_session_close: "Sitzungen werden jetzt nach {time} beendet und markiert.",
+    max_session_disabled: "Sitzungen sind nicht mehr begrenzt.",
+    flagged_sessions: "Markierte Sitzungen",
+    flagged_empty: "Es wurden noch keine Sitzungen markiert.",
+    flagged_session: "{user} in {channel} für {time}, {excess} wurden nicht gezählt",
+    role_excluded_tracking: "Die Sprachzeit von Mitgliedern mit {role} wird nicht mehr erfasst.",
+    role_excluded_leaderboard: "Mitglieder mit {role} erscheinen nicht mehr in Ranglisten.",
+    role_included: "Mitglieder mit {role} werden wieder gezählt.",
+    exclusion_modes: ["Erfassung", "Ranglisten", "aus"],
+    stage_time: "Sprechend: {speaker}, im Publikum: {audience}",
+    deleted_channel: "#{name} (gelöscht)",
+    archived_channels: "Archivierte Kanäle",
+    archive_enabled: "Die Zeit gelöschter Kanäle wird jetzt in archivierte Kanäle verschoben.",
+    archive_disabled: "Gelöschte Kanäle behalten ihre Zeit jetzt unter ihrem letzten Namen.",
+    departed_keep: "Mitglieder, die den Server verlassen haben, bleiben jetzt in Ranglisten.",
+    departed_hide: "Mitglieder, die den Server verlassen haben, werden bis zu ihrer Rückkehr ausgeblendet.",
+    departed_delete: "Mitglieder, die den Server verlassen haben, werden ausgeblendet und ihre Daten nach {days} Tagen gelöscht.",
+    departed_policies: ["behalten", "ausblenden", "löschen"],
+    webhook_set: "Sprachereignisse werden jetzt an {url} gesendet.",
+    webhook_disabled: "Sprachereignisse werden nicht mehr an einen Webhook gesendet.",
+    invalid_webhook: "Der Webhook braucht eine https-URL eines öffentlichen Hosts und ein Geheimnis.",
+};
@@ -1,26 +1,152 @@
-use std::{env, path::PathBuf, sync::Arc};
+use std::{io::ErrorKind, path::PathBuf, process::ExitCode, sync::Arc, time::Duration};
 
+use api_server::create_api_server;
+use config::Config;
 use control_server::create_control_server;
 use db::DbManager;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
+mod api_server;
 mod bot;
+mod commands;
+mod config;
 mod control_server;
 mod db;
+mod format;
+mod i18n;
+mod metrics;
+mod period;
+mod render;
+mod webhook;
 
-const SAVE_INTERVALL: u64 = 600;
+/// How long saving the DB may take when shutting down before giving up
+const SHUTDOWN_SAVE_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     dotenv::dotenv().ok();
-    // Configure the client with your Discord bot token in the environment.
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
-    let db_path = env::var("DB_PATH").expect("Expected a DB_PATH in enviroment");
-    let db: Arc<DbManager> = DbManager::open(PathBuf::from(&db_path))
-        .unwrap_or_else(|_| DbManager::new())
-        .into();
-    let db1 = db.clone();
-    create_control_server(9500, db1, &db_path);
-    if let Err(why) = bot::build_bot(&token, db.clone()).await {
-        println!("Client error: {:?}", why);
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for error in errors {
+                eprintln!("  {error}");
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&config.log_level))
+        .init();
+    let db_path = config.db_path.clone();
+    let db = DbManager::open(
+        PathBuf::from(&db_path),
+        config.defaults.clone(),
+        config.features,
+    );
+    // Starting with an empty DB after any other error would overwrite the data on the next save
+    let db: Arc<DbManager> = match db {
+        Ok(db) => db.into(),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            info!("No DB at {db_path}, starting with an empty one");
+            DbManager::new(config.defaults.clone(), config.features).into()
+        }
+        Err(err) => {
+            error!("Could not load the DB from {db_path}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // Notified by the `stop` command of the control server
+    let stop = Arc::new(Notify::new());
+    let control = create_control_server(
+        &config.control,
+        config.save_interval,
+        db.clone(),
+        &db_path,
+        stop.clone(),
+    )
+    .await;
+    if let Err(err) = control {
+        error!("Could not start the control server: {err}");
+        return ExitCode::FAILURE;
+    }
+    if let Some(api_address) = config.api_address {
+        if let Err(err) = create_api_server(api_address, config.features, db.clone()).await {
+            error!("Could not start the HTTP API on {api_address}: {err}");
+            return ExitCode::FAILURE;
+        }
+    }
+    let bot = bot::build_bot(
+        &config.discord_token,
+        db.clone(),
+        config.dev_guilds,
+        config.features,
+    );
+    let mut client = match bot.await {
+        Ok(client) => client,
+        Err(why) => {
+            error!("Client error: {:?}", why);
+            return ExitCode::FAILURE;
+        }
+    };
+    let shard_manager = client.shard_manager.clone();
+    let mut exit_code = ExitCode::SUCCESS;
+    tokio::select! {
+        result = client.start() => {
+            if let Err(why) = result {
+                error!("Client error: {:?}", why);
+            }
+            exit_code = ExitCode::FAILURE;
+        }
+        signal = shutdown_signal() => info!("Received {signal}, shutting down"),
+        _ = stop.notified() => info!("Stopped over the control server, shutting down"),
+    }
+    // No more voice state updates arrive once the shards are down
+    shard_manager.lock().await.shutdown_all().await;
+    if let Some(socket) = &config.control.socket {
+        if let Err(err) = control_server::remove_socket(socket) {
+            error!("Removing the control socket failed: {err}");
+        }
+    }
+    match tokio::time::timeout(SHUTDOWN_SAVE_TIMEOUT, db.stop_and_save_db(db_path.into())).await {
+        Ok(Ok(())) => exit_code,
+        Ok(Err(err)) => {
+            error!("Saving the DB failed: {err}");
+            ExitCode::FAILURE
+        }
+        Err(_) => {
+            error!("Saving the DB timed out");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Waits for SIGTERM or SIGINT and returns its name
+///
+/// A signal that can't be listened for is logged and never arrives
+async fn shutdown_signal() -> &'static str {
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => terminate.recv().await,
+            Err(err) => {
+                error!("Can't listen for SIGTERM: {err}");
+                std::future::pending().await
+            }
+        }
+    };
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("Can't listen for SIGINT: {err}");
+            std::future::pending::<()>().await
+        }
+    };
+    tokio::select! {
+        _ = terminate => "SIGTERM",
+        _ = interrupt => "SIGINT",
     }
 }
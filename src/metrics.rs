@@ -0,0 +1,138 @@
+//! Counters and gauges exported in the Prometheus text format
+//!
+//! Values are collected in the global [METRICS] and rendered on every scrape,
+//! active sessions are asked from the Db at that time.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::db::OpenSession;
+
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    /// Voice state updates the Db handled
+    pub voice_state_events: AtomicU64,
+    /// Messages sent to the Db that weren't handled yet
+    pub db_queue_depth: AtomicUsize,
+    /// Unix timestamp of the last successful save
+    pub last_save: AtomicU64,
+    /// Duration of the last successful save in microseconds
+    pub save_duration_micros: AtomicU64,
+    /// Responses to commands by command name and result
+    interactions: Mutex<BTreeMap<(String, &'static str), u64>>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            voice_state_events: AtomicU64::new(0),
+            db_queue_depth: AtomicUsize::new(0),
+            last_save: AtomicU64::new(0),
+            save_duration_micros: AtomicU64::new(0),
+            interactions: Mutex::new(BTreeMap::new()),
+        }
+    }
+    /// Counts a response to a command, `result` is `ok` or `error`
+    pub fn interaction(&self, command: &str, result: &'static str) {
+        *self
+            .interactions
+            .lock()
+            .unwrap()
+            .entry((command.to_string(), result))
+            .or_default() += 1;
+    }
+    /// Renders all metrics, `sessions` are the sessions going on right now
+    pub fn render(&self, sessions: &[OpenSession]) -> String {
+        let mut out = String::new();
+        let mut active = BTreeMap::new();
+        for session in sessions {
+            *active.entry(session.guild).or_insert(0u64) += 1;
+        }
+        header(
+            &mut out,
+            "voicetime_active_sessions",
+            "gauge",
+            "Users in a voice channel",
+        );
+        for (guild, count) in active {
+            writeln!(
+                out,
+                "voicetime_active_sessions{{guild=\"{guild}\"}} {count}"
+            )
+            .unwrap();
+        }
+        header(
+            &mut out,
+            "voicetime_voice_state_events_total",
+            "counter",
+            "Voice state updates processed",
+        );
+        writeln!(
+            out,
+            "voicetime_voice_state_events_total {}",
+            self.voice_state_events.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        header(
+            &mut out,
+            "voicetime_interactions_total",
+            "counter",
+            "Responses to commands by command and result",
+        );
+        for ((command, result), count) in self.interactions.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "voicetime_interactions_total{{command=\"{command}\",result=\"{result}\"}} {count}"
+            )
+            .unwrap();
+        }
+        header(
+            &mut out,
+            "voicetime_db_queue_depth",
+            "gauge",
+            "Messages waiting to be handled by the Db",
+        );
+        writeln!(
+            out,
+            "voicetime_db_queue_depth {}",
+            self.db_queue_depth.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        header(
+            &mut out,
+            "voicetime_last_save_timestamp_seconds",
+            "gauge",
+            "Unix timestamp of the last successful save, 0 if there was none",
+        );
+        writeln!(
+            out,
+            "voicetime_last_save_timestamp_seconds {}",
+            self.last_save.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        header(
+            &mut out,
+            "voicetime_save_duration_seconds",
+            "gauge",
+            "Duration of the last successful save",
+        );
+        writeln!(
+            out,
+            "voicetime_save_duration_seconds {}",
+            self.save_duration_micros.load(Ordering::Relaxed) as f64 / 1e6
+        )
+        .unwrap();
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}").unwrap();
+}
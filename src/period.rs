@@ -0,0 +1,172 @@
+use chrono::{Datelike, Duration, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+
+use crate::i18n::Catalog;
+
+/// Time range statistics can be restricted to
+/// Day boundaries are taken from the timezone of the guild
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    #[default]
+    All,
+    Today,
+    Week,
+    Month,
+    Year,
+    /// A specific calendar month, named like `2023-10`
+    Named {
+        year: i32,
+        month: u32,
+    },
+}
+
+impl Period {
+    /// The periods relative to now
+    pub const ALL: [Period; 5] = [Self::All, Self::Today, Self::Week, Self::Month, Self::Year];
+
+    pub fn name(self) -> String {
+        match self {
+            Self::All => "all".to_string(),
+            Self::Today => "today".to_string(),
+            Self::Week => "week".to_string(),
+            Self::Month => "month".to_string(),
+            Self::Year => "year".to_string(),
+            Self::Named { year, month } => format!("{year:04}-{month:02}"),
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(period) = Self::ALL.into_iter().find(|period| period.name() == name) {
+            return Some(period);
+        }
+        let (year, month) = name.split_once('-')?;
+        let (year, month) = (year.parse().ok()?, month.parse().ok()?);
+        NaiveDate::from_ymd_opt(year, month, 1)?;
+        Some(Self::Named { year, month })
+    }
+    pub fn label(self, catalog: &Catalog) -> String {
+        match self {
+            Self::All => catalog.periods[0].to_string(),
+            Self::Today => catalog.periods[1].to_string(),
+            Self::Week => catalog.periods[2].to_string(),
+            Self::Month => catalog.periods[3].to_string(),
+            Self::Year => catalog.periods[4].to_string(),
+            Self::Named { year, month } => {
+                format!("{} {year}", catalog.months[month as usize - 1])
+            }
+        }
+    }
+    /// Unix timestamps the period starts and ends at, [None] for [Period::All]
+    pub fn range(self, timezone: Tz, now: u64) -> Option<(u64, u64)> {
+        let today = timezone.timestamp_opt(now as i64, 0).single()?.date_naive();
+        let first_day = match self {
+            Self::All => return None,
+            Self::Today => today,
+            Self::Week => today - Duration::days(today.weekday().num_days_from_monday() as i64),
+            Self::Month => NaiveDate::from_ymd_opt(today.year(), today.month(), 1)?,
+            Self::Year => NaiveDate::from_ymd_opt(today.year(), 1, 1)?,
+            Self::Named { year, month } => {
+                let first_day = NaiveDate::from_ymd_opt(year, month, 1)?;
+                let next_month = (first_day + Duration::days(31)).with_day(1)?;
+                return Some((
+                    midnight(timezone, first_day)?,
+                    midnight(timezone, next_month)?,
+                ));
+            }
+        };
+        Some((midnight(timezone, first_day)?, u64::MAX))
+    }
+}
+
+/// Unix timestamp of the start of `day` in `timezone`
+fn midnight(timezone: Tz, day: NaiveDate) -> Option<u64> {
+    // Midnight might not exist or be ambiguous on days with a DST change
+    let midnight = day.and_hms_opt(0, 0, 0)?;
+    let start = timezone
+        .from_local_datetime(&midnight)
+        .earliest()
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(midnight + Duration::hours(1)))
+                .earliest()
+        })?;
+    Some(start.timestamp().max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::{America::Sao_Paulo, Europe::Berlin};
+
+    use super::*;
+
+    fn named(year: i32, month: u32) -> Period {
+        Period::Named { year, month }
+    }
+
+    #[test]
+    fn named_months() {
+        // Starts in winter time and ends in summer time
+        assert_eq!(
+            named(2023, 3).range(Berlin, 0),
+            Some((1677625200, 1680300000))
+        );
+        // Starts in summer time and ends in winter time
+        assert_eq!(
+            named(2023, 10).range(Berlin, 0),
+            Some((1696111200, 1698793200))
+        );
+        assert_eq!(
+            named(2023, 12).range(Berlin, 0),
+            Some((1701385200, 1704063600))
+        );
+        assert_eq!(
+            named(2024, 2).range(Berlin, 0),
+            Some((1706742000, 1709247600))
+        );
+    }
+
+    #[test]
+    fn relative_periods() {
+        // 2023-11-01 10:00 in Berlin, a Wednesday
+        let now = 1698829200;
+        assert_eq!(Period::All.range(Berlin, now), None);
+        assert_eq!(
+            Period::Today.range(Berlin, now),
+            Some((1698793200, u64::MAX))
+        );
+        assert_eq!(
+            Period::Week.range(Berlin, now),
+            Some((1698620400, u64::MAX))
+        );
+        assert_eq!(
+            Period::Month.range(Berlin, now),
+            Some((1698793200, u64::MAX))
+        );
+        assert_eq!(
+            Period::Year.range(Berlin, now),
+            Some((1672527600, u64::MAX))
+        );
+    }
+
+    #[test]
+    fn month_starts_in_the_guild_timezone() {
+        // 2024-01-31 23:30 in UTC is already February in Berlin
+        assert_eq!(
+            Period::Month.range(Berlin, 1706743800),
+            Some((1706742000, u64::MAX))
+        );
+    }
+
+    #[test]
+    fn day_with_dst_change() {
+        // 2023-03-26 12:00 in Berlin, the day only has 23 hours
+        assert_eq!(
+            Period::Today.range(Berlin, 1679824800),
+            Some((1679785200, u64::MAX))
+        );
+        // Clocks jumped from midnight to 01:00 in São Paulo on 2018-11-04
+        assert_eq!(
+            Period::Today.range(Sao_Paulo, 1541340000),
+            Some((1541300400, u64::MAX))
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::Cursor;
+
+use font8x8::{UnicodeFonts, BASIC_FONTS, LATIN_FONTS};
+use image::{ImageOutputFormat, Rgb, RgbImage};
+
+const BACKGROUND: Rgb<u8> = Rgb([0x31, 0x33, 0x38]);
+const FOREGROUND: Rgb<u8> = Rgb([0xf2, 0xf3, 0xf5]);
+const EMPTY_CELL: Rgb<u8> = Rgb([0x2b, 0x2d, 0x31]);
+const ACCENT: Rgb<u8> = Rgb([0x58, 0x65, 0xf2]);
+
+const GLYPH_SIZE: u32 = 8;
+
+/// Small drawing surface on top of an [RgbImage]
+struct Canvas {
+    image: RgbImage,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            image: RgbImage::from_pixel(width, height, BACKGROUND),
+        }
+    }
+    /// Fills a rectangle, clipping everything outside of the canvas
+    fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: Rgb<u8>) {
+        let x_end = (x + width).min(self.image.width());
+        let y_end = (y + height).min(self.image.height());
+        for py in y..y_end {
+            for px in x..x_end {
+                self.image.put_pixel(px, py, color);
+            }
+        }
+    }
+    /// Draws text with the built-in 8x8 bitmap font
+    /// Characters without a glyph are drawn as `?`
+    fn draw_text(&mut self, x: u32, y: u32, text: &str, scale: u32, color: Rgb<u8>) {
+        for (i, char) in text.chars().enumerate() {
+            let glyph = BASIC_FONTS
+                .get(char)
+                .or_else(|| LATIN_FONTS.get(char))
+                .or_else(|| BASIC_FONTS.get('?'))
+                .unwrap_or_default();
+            let origin = x + i as u32 * GLYPH_SIZE * scale;
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..GLYPH_SIZE {
+                    if bits & (1 << column) != 0 {
+                        self.fill_rect(
+                            origin + column * scale,
+                            y + row as u32 * scale,
+                            scale,
+                            scale,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+    /// Encodes the canvas as PNG
+    fn into_png(self) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        self.image
+            .write_to(&mut buffer, ImageOutputFormat::Png)
+            .expect("Encoding an in-memory PNG can't fail");
+        buffer.into_inner()
+    }
+}
+
+/// Width of `text` in pixels when drawn with the given scale
+fn text_width(text: &str, scale: u32) -> u32 {
+    text.chars().count() as u32 * GLYPH_SIZE * scale
+}
+
+/// Linear interpolation between the empty cell color and the accent color
+fn heat_color(value: u64, max: u64) -> Rgb<u8> {
+    if value == 0 || max == 0 {
+        return EMPTY_CELL;
+    }
+    let ratio = value as f64 / max as f64;
+    let low = Rgb([0x2f, 0x34, 0x58]);
+    let channel =
+        |i: usize| (low.0[i] as f64 + (ACCENT.0[i] as f64 - low.0[i] as f64) * ratio) as u8;
+    Rgb([channel(0), channel(1), channel(2)])
+}
+
+/// Renders a 7x24 hour-of-week heatmap as PNG
+///
+/// `buckets` is indexed by weekday (monday first) and hour of the day,
+/// `weekdays` are the row labels in the same order
+pub fn heatmap(title: &str, weekdays: &[&str; 7], buckets: &[[u64; 24]; 7]) -> Vec<u8> {
+    const CELL: u32 = 28;
+    const GAP: u32 = 3;
+    const MARGIN: u32 = 16;
+    const LABEL_WIDTH: u32 = 3 * GLYPH_SIZE * 2 + 8;
+    const TITLE_HEIGHT: u32 = GLYPH_SIZE * 2 + 16;
+    const HOUR_LABEL_HEIGHT: u32 = GLYPH_SIZE + 8;
+    let grid_width = 24 * (CELL + GAP) - GAP;
+    let grid_height = 7 * (CELL + GAP) - GAP;
+    let width = MARGIN * 2 + LABEL_WIDTH + grid_width;
+    let height = MARGIN * 2 + TITLE_HEIGHT + HOUR_LABEL_HEIGHT + grid_height;
+    let mut canvas = Canvas::new(width, height);
+    canvas.draw_text(MARGIN, MARGIN, title, 2, FOREGROUND);
+    let grid_x = MARGIN + LABEL_WIDTH;
+    let grid_y = MARGIN + TITLE_HEIGHT + HOUR_LABEL_HEIGHT;
+    for hour in (0..24).step_by(3) {
+        let label = format!("{hour}");
+        let x = grid_x + hour * (CELL + GAP) + (CELL - text_width(&label, 1)) / 2;
+        canvas.draw_text(x, grid_y - HOUR_LABEL_HEIGHT, &label, 1, FOREGROUND);
+    }
+    let max = buckets.iter().flatten().copied().max().unwrap_or(0);
+    for (day, hours) in buckets.iter().enumerate() {
+        let y = grid_y + day as u32 * (CELL + GAP);
+        canvas.draw_text(
+            MARGIN,
+            y + (CELL - GLYPH_SIZE * 2) / 2,
+            weekdays[day],
+            2,
+            FOREGROUND,
+        );
+        for (hour, value) in hours.iter().enumerate() {
+            let x = grid_x + hour as u32 * (CELL + GAP);
+            canvas.fill_rect(x, y, CELL, CELL, heat_color(*value, max));
+        }
+    }
+    canvas.into_png()
+}
+
+/// Shortens `text` to at most `max` characters
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_string()
+    } else {
+        let mut text: String = text.chars().take(max - 2).collect();
+        text.push_str("..");
+        text
+    }
+}
+
+/// Renders a horizontal bar chart as PNG
+///
+/// Every entry consists of a label, the value the bar length is based on
+/// and the text shown next to the bar
+pub fn bar_chart(title: &str, entries: &[(String, u64, String)]) -> Vec<u8> {
+    const WIDTH: u32 = 900;
+    const MARGIN: u32 = 16;
+    const ROW: u32 = 32;
+    const BAR: u32 = 22;
+    const LABEL_CHARS: usize = 20;
+    const VALUE_CHARS: usize = 16;
+    const TITLE_HEIGHT: u32 = GLYPH_SIZE * 2 + 16;
+    let label_width = LABEL_CHARS as u32 * GLYPH_SIZE * 2 + 8;
+    let value_width = VALUE_CHARS as u32 * GLYPH_SIZE + 8;
+    let bar_width = WIDTH - MARGIN * 2 - label_width - value_width;
+    let height = MARGIN * 2 + TITLE_HEIGHT + entries.len().max(1) as u32 * ROW;
+    let mut canvas = Canvas::new(WIDTH, height);
+    canvas.draw_text(MARGIN, MARGIN, &truncate(title, 50), 2, FOREGROUND);
+    let max = entries.iter().map(|entry| entry.1).max().unwrap_or(0);
+    for (i, (label, value, value_label)) in entries.iter().enumerate() {
+        let y = MARGIN + TITLE_HEIGHT + i as u32 * ROW;
+        canvas.draw_text(
+            MARGIN,
+            y + (ROW - GLYPH_SIZE * 2) / 2,
+            &truncate(label, LABEL_CHARS),
+            2,
+            FOREGROUND,
+        );
+        let length = if max == 0 {
+            0
+        } else {
+            ((*value as f64 / max as f64) * bar_width as f64) as u32
+        };
+        let bar_x = MARGIN + label_width;
+        canvas.fill_rect(bar_x, y + (ROW - BAR) / 2, length.max(2), BAR, ACCENT);
+        canvas.draw_text(
+            bar_x + length.max(2) + 8,
+            y + (ROW - GLYPH_SIZE) / 2,
+            &truncate(value_label, VALUE_CHARS),
+            1,
+            FOREGROUND,
+        );
+    }
+    canvas.into_png()
+}
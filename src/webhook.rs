@@ -0,0 +1,372 @@
+//! Outgoing webhooks guilds can configure with `/settings webhook`
+//!
+//! Events are POSTed as JSON. The body is signed with HMAC-SHA256 using the secret of the
+//! webhook, the hex encoded signature is sent as `X-Voicetime-Signature: sha256=<signature>`.
+//! Failed deliveries are retried with exponential backoff.
+//!
+//! Only `https` URLs of public hosts are allowed, so guild admins can't make the bot send
+//! requests to itself or other services in its network. Hosts are resolved and checked
+//! before sending, redirects aren't followed. With `features.webhooks_allow_private`
+//! plain `http` and local hosts are allowed as well, to try webhooks out against
+//! `examples/webhook_receiver.rs`.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use reqwest::{redirect, Client, StatusCode, Url};
+use ring::hmac;
+use serde_json::{json, Value};
+use serenity::model::prelude::{ChannelId, GuildId, UserId};
+use tracing::warn;
+
+/// Hours of voice time in a guild a milestone is sent for
+pub const MILESTONE_HOURS: [u64; 8] = [1, 10, 25, 50, 100, 250, 500, 1000];
+
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: String,
+}
+
+/// Something a [Webhook] is notified about
+pub enum WebhookEvent {
+    /// A session ended, `credited` is less than `duration` if it was capped
+    SessionEnd {
+        guild: GuildId,
+        channel: ChannelId,
+        user: UserId,
+        start: u64,
+        duration: u64,
+        credited: u64,
+    },
+    /// The voice time of a user in the guild reached one of [MILESTONE_HOURS]
+    Milestone {
+        guild: GuildId,
+        user: UserId,
+        hours: u64,
+    },
+    /// A user opted out of or back into tracking
+    OptOut {
+        guild: GuildId,
+        user: UserId,
+        opted_out: bool,
+    },
+}
+
+impl WebhookEvent {
+    fn to_json(&self, timestamp: u64) -> Value {
+        let id = |id: u64| id.to_string();
+        match self {
+            Self::SessionEnd {
+                guild,
+                channel,
+                user,
+                start,
+                duration,
+                credited,
+            } => json!({
+                "event": "session_end",
+                "timestamp": timestamp,
+                "guild": id(guild.0),
+                "channel": id(channel.0),
+                "user": id(user.0),
+                "start": start,
+                "duration": duration,
+                "credited": credited,
+            }),
+            Self::Milestone { guild, user, hours } => json!({
+                "event": "milestone",
+                "timestamp": timestamp,
+                "guild": id(guild.0),
+                "user": id(user.0),
+                "hours": hours,
+            }),
+            Self::OptOut {
+                guild,
+                user,
+                opted_out,
+            } => json!({
+                "event": "opt_out",
+                "timestamp": timestamp,
+                "guild": id(guild.0),
+                "user": id(user.0),
+                "opted_out": opted_out,
+            }),
+        }
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::sign(&key, body)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Whether the URL can be used for a webhook, its host isn't resolved yet
+///
+/// `allow_private` also allows `http` and hosts that aren't public
+pub fn is_allowed_url(url: &str, allow_private: bool) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    if allow_private {
+        return matches!(url.scheme(), "http" | "https") && url.host_str().is_some();
+    }
+    url.scheme() == "https"
+        && match url.host_str() {
+            Some(host) => match host_ip(host) {
+                Some(ip) => is_public(ip),
+                None => {
+                    let domain = host.trim_end_matches('.');
+                    domain != "localhost" && !domain.ends_with(".localhost")
+                }
+            },
+            None => false,
+        }
+}
+
+/// The address of a host given as IP, IPv6 addresses are enclosed in brackets in URLs
+fn host_ip(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Whether the address is reachable over the internet and not loopback, private or link-local
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // Shared address space of carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // "This network"
+        || a == 0)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local addresses fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local addresses fe80::/10
+        || (first & 0xffc0) == 0xfe80)
+}
+
+/// Whether a delivery answered with `status` is tried again, other failures are final
+fn is_retried(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Builds a client that connects to a checked address of the webhook host
+///
+/// The host is resolved here and the client is pinned to the result, so the host can't
+/// resolve to a public address for the check and a private one for the request.
+/// With `allow_private` the host is resolved as usual
+async fn pinned_client(url: &str, allow_private: bool) -> Result<Client, String> {
+    if !is_allowed_url(url, allow_private) {
+        return Err(match allow_private {
+            true => "only http and https URLs are allowed".to_string(),
+            false => "only https URLs of public hosts are allowed".to_string(),
+        });
+    }
+    let url = Url::parse(url).map_err(|err| err.to_string())?;
+    let builder = Client::builder().redirect(redirect::Policy::none());
+    let domain = url
+        .host_str()
+        .filter(|host| !allow_private && host_ip(host).is_none());
+    let builder = match domain {
+        Some(domain) => {
+            let port = url.port_or_known_default().unwrap_or(443);
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(|err| format!("resolving {domain} failed: {err}"))?
+                .collect();
+            if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+                return Err(format!("{domain} resolves to {}", address.ip()));
+            }
+            builder.resolve_to_addrs(domain, &addresses)
+        }
+        None => builder,
+    };
+    builder.build().map_err(|err| err.to_string())
+}
+
+/// Sends the event, retrying network errors, rate limits and server errors
+///
+/// `timestamp` is the unix timestamp the event happened at,
+/// `allow_private` is `features.webhooks_allow_private` of the config
+pub async fn deliver(webhook: Webhook, event: WebhookEvent, timestamp: u64, allow_private: bool) {
+    let client = match pinned_client(&webhook.url, allow_private).await {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("Webhook {} not sent: {err}", webhook.url);
+            return;
+        }
+    };
+    let body = event.to_json(timestamp).to_string();
+    let signature = format!("sha256={}", sign(&webhook.secret, body.as_bytes()));
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = client
+            .post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .header("X-Voicetime-Signature", &signature)
+            .timeout(REQUEST_TIMEOUT)
+            .body(body.clone())
+            .send()
+            .await;
+        let error = match result {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => {
+                let status = response.status();
+                if !is_retried(status) {
+                    warn!("Webhook {} rejected the event: {status}", webhook.url);
+                    return;
+                }
+                status.to_string()
+            }
+            Err(err) => err.to_string(),
+        };
+        warn!(
+            "Webhook {} failed (attempt {attempt}/{MAX_ATTEMPTS}): {error}",
+            webhook.url
+        );
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_known_vector() {
+        // Test case 2 of RFC 4231
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in [
+            "1.1.1.1",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn non_public_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "172.31.255.255",
+            "192.168.1.1",
+            "100.64.0.1",
+            "100.127.255.255",
+            "169.254.169.254",
+            "0.0.0.0",
+            "0.1.2.3",
+            "255.255.255.255",
+            "224.0.0.1",
+            "::1",
+            "::",
+            "::ffff:127.0.0.1",
+            "::ffff:192.168.1.1",
+            "::ffff:169.254.169.254",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "ff02::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn allowed_urls() {
+        assert!(is_allowed_url("https://example.com/hook", false));
+        assert!(is_allowed_url("https://1.1.1.1:8443/hook", false));
+        assert!(is_allowed_url("https://[2606:4700::1111]/hook", false));
+        for url in [
+            "http://example.com/hook",
+            "ftp://example.com/hook",
+            "not a url",
+            "https://localhost/hook",
+            "https://api.localhost./hook",
+            "https://127.0.0.1/hook",
+            "https://10.1.2.3/hook",
+            "https://192.168.0.10/hook",
+            "https://100.100.100.100/hook",
+            "https://169.254.169.254/latest/meta-data",
+            "https://[::1]/hook",
+            "https://[::ffff:10.0.0.1]/hook",
+            "https://[fd00::1]/hook",
+            "https://[fe80::1]/hook",
+        ] {
+            assert!(!is_allowed_url(url, false), "{url}");
+        }
+    }
+
+    #[test]
+    fn allowed_urls_with_private() {
+        assert!(is_allowed_url("http://127.0.0.1:9000/hook", true));
+        assert!(is_allowed_url("http://localhost:9000/hook", true));
+        assert!(is_allowed_url("https://192.168.0.10/hook", true));
+        assert!(!is_allowed_url("ftp://127.0.0.1/hook", true));
+        assert!(!is_allowed_url("file:///etc/passwd", true));
+    }
+
+    #[test]
+    fn retried_statuses() {
+        for status in [429, 500, 502, 503, 504] {
+            assert!(
+                is_retried(StatusCode::from_u16(status).unwrap()),
+                "{status}"
+            );
+        }
+        for status in [200, 204, 301, 400, 401, 403, 404, 410, 422] {
+            assert!(
+                !is_retried(StatusCode::from_u16(status).unwrap()),
+                "{status}"
+            );
+        }
+    }
+}